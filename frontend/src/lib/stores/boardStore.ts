@@ -2,6 +2,7 @@ import { writable, derived, get } from "svelte/store";
 import type { Writable, Readable } from "svelte/store";
 
 export type TeamType = "red" | "blue" | "neutral" | "assassin";
+export type TurnType = "red" | "blue";
 export type PublicTeamType = TeamType | "unknown";
 
 export interface PublicCard {
@@ -20,47 +21,223 @@ export type SpymasterBoard = SpymasterCard[][];
 
 export type BoardMode = "public" | "spymaster";
 
+export interface GameOver {
+    winner: TurnType;
+    reason: "all_agents_found" | "assassin";
+}
+
+export interface Clue {
+    team: TurnType;
+    word: string;
+    number: number;
+}
+
+export interface Guess {
+    row: number;
+    col: number;
+    team: TeamType;
+    player: number | null;
+}
+
+export interface TurnRecord {
+    clue: Clue;
+    guesses: Guess[];
+    passed: boolean;
+}
+
+export interface GameData {
+    board: PublicBoard | SpymasterBoard;
+    turn: TurnType;
+    game_over: GameOver | null;
+    key?: SpymasterBoard | null;
+    history: TurnRecord[];
+    // Determines the whole board, so only spymasters see it before the game is over
+    code?: string | null;
+}
+
+export interface Snapshot extends GameData {
+    seq: number;
+    seats: Seats;
+    players: Player[];
+}
+
+export interface Seats {
+    blue: boolean;
+    red: boolean;
+}
+
+export interface Seat {
+    team: TurnType;
+    seat_token: string;
+}
+
+export type RoleType = "operative" | "spymaster";
+
+export interface Player {
+    id: number;
+    nickname: string;
+    team: TurnType;
+    role: RoleType;
+    connected: boolean;
+}
+
+export interface ChatMessage {
+    player: Player;
+    text: string;
+}
+
+export interface ErrorData {
+    id?: string;
+    error: string;
+    message: string;
+    details?: unknown;
+}
+
+export interface WordPack {
+    name: string;
+    words: number;
+    // Whether games that don't choose packs are dealt from it
+    default: boolean;
+    // Whether it was uploaded through the API, rather than built in or loaded from a file
+    uploaded: boolean;
+}
+
+export interface PackWeight {
+    pack: string;
+    weight: number;
+}
+
+export interface UndoVotes {
+    votes: number;
+    needed: number;
+}
+
 export interface BoardState {
     board: PublicBoard | SpymasterBoard;
+    turn: TurnType | null;
+    gameOver: GameOver | null;
+    history: TurnRecord[];
+    code: string | null;
+    // Whether the latest action was a reveal, which may still be undone
+    canUndo: boolean;
+    undoVotes: UndoVotes | null;
+    seats: Seats;
+    mySeat: Seat | null;
+    players: Player[];
+    myPlayer: Player | null;
+    chat: ChatMessage[];
     mode: BoardMode;
     loading: boolean;
     error: string | null;
     connected: boolean;
+    // Set when the server announces it is restarting, until the new one sends a snapshot
+    restarting: boolean;
     reconnectAttempts: number;
 }
 
 export interface RevealRequest {
     row: number;
     col: number;
+    team: TurnType;
+}
+
+export interface Session {
+    player: Player;
+    seat: Seat | null;
 }
 
 export interface WsMessage {
-    type: "card_revealed" | "new_game";
+    // Sequence number of game events, used to notice missed ones
+    seq?: number;
+    type:
+        | "snapshot"
+        | "card_revealed"
+        | "card_hidden"
+        | "undo_vote"
+        | "new_game"
+        | "turn_changed"
+        | "turn_passed"
+        | "game_over"
+        | "clue_given"
+        | "seats_changed"
+        | "player_joined"
+        | "player_left"
+        | "player_changed_team"
+        | "player_connected"
+        | "player_disconnected"
+        | "chat"
+        | "ack"
+        | "error"
+        | "room_expired"
+        | "server_shutting_down"
+        | "hello";
     data:
         | {
               row: number;
               col: number;
               new_card_state: SpymasterCard;
           }
-        | PublicBoard
-        | SpymasterBoard;
+        | { turn: TurnType }
+        | { team: TurnType }
+        | { code: string }
+        | { reconnect_after_ms: number }
+        | Seats
+        | Player
+        | ChatMessage
+        | ErrorData
+        | { id: string | null; result: unknown }
+        | { version: number; supported: number[] }
+        | { row: number; col: number; game: GameData }
+        | UndoVotes
+        | (GameOver & { key: SpymasterBoard })
+        | Clue
+        | GameData
+        | Snapshot;
 }
 
+// The WebSocket protocol version this store understands
+const PROTOCOL_VERSION = 2;
+
 class BoardStore {
     private _store: Writable<BoardState>;
     private _ws: WebSocket | null = null;
+    private _eventSource: EventSource | null = null;
     private _reconnectTimer: number | null = null;
     private _maxReconnectAttempts = 5;
     private _reconnectDelay = 1000;
     private _mode: "public" | "spymaster" = "public";
+    private _room = "";
+    private _token: string | null = null;
+    private _session: string | null = null;
+    private _nextRequestId = 1;
+    private _expired = false;
+    // How long the server asked us to wait before reconnecting after it shuts down
+    private _restartDelay: number | null = null;
+    // Whether the current connection has delivered anything yet
+    private _synced = false;
+    // The last game event applied, and the id of the replay request sent after a gap, if any
+    private _lastSeq: number | null = null;
+    private _resyncId: string | null = null;
 
     constructor() {
         this._store = writable<BoardState>({
             board: [],
+            turn: null,
+            gameOver: null,
+            history: [],
+            code: null,
+            canUndo: false,
+            undoVotes: null,
+            seats: { blue: false, red: false },
+            mySeat: null,
+            players: [],
+            myPlayer: null,
+            chat: [],
             mode: "public",
             loading: false,
             error: null,
             connected: false,
+            restarting: false,
             reconnectAttempts: 0
         });
     }
@@ -77,7 +254,7 @@ class BoardStore {
         return derived(this._store, ($store) => $store.connected);
     }
 
-    async initialize(mode: BoardMode): Promise<void> {
+    async initialize(mode: BoardMode, room: string, token: string | null = null): Promise<void> {
         this._store.update((state) => ({
             ...state,
             mode,
@@ -85,46 +262,160 @@ class BoardStore {
             error: null
         }));
         this._mode = mode;
+        this._room = room;
+        this._token = token;
+        this._expired = false;
+        this._lastSeq = null;
+        this._eventSource?.close();
+        this._eventSource = null;
+        this._loadSession();
 
         try {
-            const endpoint = mode === "public" ? "/api/board/public" : "/api/board/spymaster";
-            const response = await fetch(endpoint);
+            await this._resumeSession();
+
+            // The board, seats and roster arrive as a snapshot once the WebSocket opens
+            this._initWebSocket();
+        } catch (error) {
+            this._store.update((state) => ({
+                ...state,
+                loading: false,
+                error: error instanceof Error ? error.message : "Failed to load board"
+            }));
+        }
+    }
+
+    async claimSeat(team: TurnType): Promise<void> {
+        try {
+            const response = await fetch(`/api/${this._room}/seats/${team}/claim`, {
+                method: "POST",
+                headers: this._headers()
+            });
 
             if (!response.ok) {
-                throw new Error(`HTTP ${response.status}: ${response.statusText}`);
+                const body = await response.json().catch(() => null);
+                throw new Error(body?.message ?? `HTTP ${response.status}: ${response.statusText}`);
             }
 
-            const boardData = await response.json();
+            const seat: Seat = await response.json();
+            localStorage.setItem(`codenames:seat:${this._room}`, JSON.stringify(seat));
+            this._store.update((state) => ({ ...state, mySeat: seat }));
+            // Reconnect so the server knows which seat this connection holds
+            this._initWebSocket();
+        } catch (error) {
+            this._store.update((state) => ({
+                ...state,
+                error: error instanceof Error ? error.message : "Failed to claim seat"
+            }));
+        }
+    }
+
+    async releaseSeat(): Promise<void> {
+        const { mySeat } = get(this._store);
+        if (!mySeat) return;
+
+        try {
+            const response = await fetch(`/api/${this._room}/seats/${mySeat.team}/release`, {
+                method: "POST",
+                headers: this._headers()
+            });
+
+            if (!response.ok) {
+                const body = await response.json().catch(() => null);
+                throw new Error(body?.message ?? `HTTP ${response.status}: ${response.statusText}`);
+            }
 
+            localStorage.removeItem(`codenames:seat:${this._room}`);
+            this._store.update((state) => ({ ...state, mySeat: null }));
+            this._initWebSocket();
+        } catch (error) {
             this._store.update((state) => ({
                 ...state,
-                board: boardData,
-                loading: false,
-                error: null
+                error: error instanceof Error ? error.message : "Failed to release seat"
             }));
+        }
+    }
+
+    async join(nickname: string, team: TurnType, role: RoleType): Promise<void> {
+        try {
+            const response = await fetch(`/api/${this._room}/players`, {
+                method: "POST",
+                headers: this._headers(),
+                body: JSON.stringify({ nickname, team, role })
+            });
 
+            if (!response.ok) {
+                const body = await response.json().catch(() => null);
+                throw new Error(body?.message ?? `HTTP ${response.status}: ${response.statusText}`);
+            }
+
+            const { player, session_token }: { player: Player; session_token: string } =
+                await response.json();
+            this._session = session_token;
+            localStorage.setItem(`codenames:session:${this._room}`, session_token);
+            this._store.update((state) => ({ ...state, myPlayer: player }));
+            // Reconnect so the server ties this connection to the player
             this._initWebSocket();
         } catch (error) {
             this._store.update((state) => ({
                 ...state,
-                loading: false,
-                error: error instanceof Error ? error.message : "Failed to load board"
+                error: error instanceof Error ? error.message : "Failed to join the game"
             }));
         }
     }
 
+    private _loadSession(): void {
+        const savedSeat = localStorage.getItem(`codenames:seat:${this._room}`);
+        const mySeat: Seat | null = savedSeat ? JSON.parse(savedSeat) : null;
+        this._session = localStorage.getItem(`codenames:session:${this._room}`);
+        this._store.update((state) => ({ ...state, mySeat, myPlayer: null }));
+    }
+
+    // Picks the player (and the seat they claimed) back up after a reload
+    private async _resumeSession(): Promise<void> {
+        if (!this._session) return;
+
+        const response = await fetch(`/api/${this._room}/players/me`, { headers: this._headers() });
+        if (!response.ok) {
+            // The session ended while we were away
+            if (response.status === 401) this._forgetSession();
+            return;
+        }
+
+        const { player, seat }: Session = await response.json();
+        if (seat) {
+            localStorage.setItem(`codenames:seat:${this._room}`, JSON.stringify(seat));
+        }
+        this._store.update((state) => ({ ...state, myPlayer: player, mySeat: seat ?? state.mySeat }));
+    }
+
+    private _forgetSession(): void {
+        this._session = null;
+        localStorage.removeItem(`codenames:session:${this._room}`);
+        this._store.update((state) => ({ ...state, myPlayer: null }));
+    }
+
+    // The team a guess is made for: a player's own, so the server can refuse it out of turn, or
+    // the current one when a room without players is played from a shared screen
+    private _guessingTeam(): TurnType | null {
+        const { turn, myPlayer } = get(this._store);
+        return myPlayer?.team ?? turn;
+    }
+
     async revealCard(row: number, col: number): Promise<void> {
+        const team = this._guessingTeam();
+        if (!team) return;
+
         try {
-            const response = await fetch("/api/reveal", {
+            const request: RevealRequest = { row, col, team };
+            const response = await fetch(`/api/${this._room}/reveal`, {
                 method: "POST",
-                headers: {
-                    "Content-Type": "application/json"
-                },
-                body: JSON.stringify({ row, col })
+                headers: this._headers(),
+                body: JSON.stringify(request)
             });
 
             if (!response.ok) {
-                throw new Error(`HTTP ${response.status}: ${response.statusText}`);
+                const body = await response.json().catch(() => null);
+                throw new Error(body?.message ?? `HTTP ${response.status}: ${response.statusText}`);
             }
         } catch (error) {
             this._store.update((state) => ({
@@ -134,23 +425,106 @@ class BoardStore {
         }
     }
 
-    async newGame(): Promise<void> {
+    async giveClue(word: string, number: number): Promise<void> {
         try {
+            const clue = { word, number };
+            const response = await fetch(`/api/${this._room}/clue`, {
+                method: "POST",
+                headers: this._headers(),
+                body: JSON.stringify(clue)
+            });
+
+            if (!response.ok) {
+                const body = await response.json().catch(() => null);
+                throw new Error(body?.message ?? `HTTP ${response.status}: ${response.statusText}`);
+            }
+        } catch (error) {
             this._store.update((state) => ({
                 ...state,
-                loading: true
+                error: error instanceof Error ? error.message : "Failed to give clue"
             }));
+        }
+    }
+
+    async endTurn(): Promise<void> {
+        const team = this._guessingTeam();
+        if (!team) return;
 
-            const response = await fetch("/api/new_game", {
+        try {
+            const response = await fetch(`/api/${this._room}/end_turn`, {
                 method: "POST",
-                headers: {
-                    "Content-Type": "application/json"
-                },
+                headers: this._headers(),
+                body: JSON.stringify({ team })
+            });
+
+            if (!response.ok) {
+                const body = await response.json().catch(() => null);
+                throw new Error(body?.message ?? `HTTP ${response.status}: ${response.statusText}`);
+            }
+        } catch (error) {
+            this._store.update((state) => ({
+                ...state,
+                error: error instanceof Error ? error.message : "Failed to end turn"
+            }));
+        }
+    }
+
+    async undo(): Promise<void> {
+        await this._post("undo", "Failed to undo the last reveal");
+    }
+
+    async voteUndo(): Promise<void> {
+        await this._post("undo/vote", "Failed to vote to undo");
+    }
+
+    private async _post(action: string, failure: string): Promise<void> {
+        try {
+            const response = await fetch(`/api/${this._room}/${action}`, {
+                method: "POST",
+                headers: this._headers(),
                 body: "{}"
             });
 
             if (!response.ok) {
-                throw new Error(`HTTP ${response.status}: ${response.statusText}`);
+                const body = await response.json().catch(() => null);
+                throw new Error(body?.message ?? `HTTP ${response.status}: ${response.statusText}`);
+            }
+        } catch (error) {
+            this._store.update((state) => ({
+                ...state,
+                error: error instanceof Error ? error.message : failure
+            }));
+        }
+    }
+
+    // The word packs new games can be dealt from
+    async wordPacks(): Promise<WordPack[]> {
+        try {
+            const response = await fetch("/api/wordpacks");
+            return response.ok ? await response.json() : [];
+        } catch {
+            return [];
+        }
+    }
+
+    // Deals a random board, or the one a game code determines, from the chosen word packs (or the
+    // server's default ones if none are chosen)
+    async newGame(code?: string, packs: PackWeight[] = []): Promise<void> {
+        try {
+            this._store.update((state) => ({
+                ...state,
+                loading: true
+            }));
+
+            const response = await fetch(`/api/${this._room}/new_game`, {
+                method: "POST",
+                headers: this._headers(),
+                body: JSON.stringify(code ? { code, packs } : { packs })
+            });
+
+            if (!response.ok) {
+                const body = await response.json().catch(() => null);
+                throw new Error(body?.message ?? `HTTP ${response.status}: ${response.statusText}`);
             }
         } catch (error) {
             this._store.update((state) => ({
@@ -160,19 +534,86 @@ class BoardStore {
         }
     }
 
+    sendChat(text: string): void {
+        this._sendCommand("chat", { text });
+    }
+
+    // Sends a command over the WebSocket, returning its id; failures come back as an `error`
+    // message with the same id
+    private _sendCommand(type: string, data: unknown): string | null {
+        if (this._ws?.readyState !== WebSocket.OPEN) {
+            this._store.update((state) => ({ ...state, error: "Not connected to the server" }));
+            return null;
+        }
+        const id = String(this._nextRequestId++);
+        this._ws.send(JSON.stringify({ type, id, data }));
+        return id;
+    }
+
+    // Whether a game event should be applied; asks for a replay when events were missed
+    private _acceptSeq(seq: number): boolean {
+        if (this._lastSeq === null) {
+            this._lastSeq = seq;
+            return true;
+        }
+        if (seq <= this._lastSeq) {
+            // Already applied, e.g. replayed alongside the live broadcast
+            return false;
+        }
+        if (seq > this._lastSeq + 1) {
+            if (this._eventSource) {
+                // Reopening the stream replays what was missed
+                this._initEventSource();
+            } else if (!this._resyncId) {
+                this._resyncId = this._sendCommand("resync", { last_seq: this._lastSeq });
+            }
+            return false;
+        }
+        this._lastSeq = seq;
+        return true;
+    }
+
+    private _headers(): Record<string, string> {
+        const headers: Record<string, string> = { "Content-Type": "application/json" };
+        if (this._token) {
+            headers["Authorization"] = `Bearer ${this._token}`;
+        }
+        const { mySeat } = get(this._store);
+        if (mySeat) {
+            headers["X-Seat-Token"] = mySeat.seat_token;
+        }
+        if (this._session) {
+            headers["X-Session-Token"] = this._session;
+        }
+        return headers;
+    }
+
     clearError(): void {
         this._store.update((state) => ({ ...state, error: null }));
     }
 
+    // Query parameters shared by the WebSocket and the event stream
+    private _connectionParams(): URLSearchParams {
+        const params = new URLSearchParams({ protocol: String(PROTOCOL_VERSION) });
+        if (this._token) params.set("token", this._token);
+        const { mySeat } = get(this._store);
+        if (mySeat) params.set("seat", mySeat.seat_token);
+        if (this._session) params.set("session", this._session);
+        if (this._lastSeq !== null) params.set("last_seq", String(this._lastSeq));
+        return params;
+    }
+
     private _initWebSocket(): void {
         if (this._ws) {
             this._ws.close();
         }
 
         try {
-            const wsUrl = `/ws/${this._mode}`;
+            const wsUrl = `/ws/${this._room}/${this._mode}?${this._connectionParams()}`;
 
             this._ws = new WebSocket(wsUrl);
+            this._synced = false;
+            this._resyncId = null;
 
             this._ws.onopen = () => {
                 console.log("WebSocket connected");
@@ -191,6 +632,8 @@ class BoardStore {
             this._ws.onmessage = (event) => {
                 try {
                     const message: WsMessage = JSON.parse(event.data);
+                    this._synced = true;
+                    if (message.seq !== undefined && !this._acceptSeq(message.seq)) return;
                     this._handleWebSocketMessage(message);
                 } catch (error) {
                     console.error("Failed to parse WebSocket message:", error);
@@ -204,7 +647,19 @@ class BoardStore {
                     connected: false
                 }));
 
-                this._attemptReconnect();
+                if (this._expired) return;
+                if (this._restartDelay !== null) {
+                    // The server hung up on purpose; give it time to come back before retrying
+                    const delay = this._restartDelay;
+                    this._restartDelay = null;
+                    this._store.update((state) => ({ ...state, reconnectAttempts: 0 }));
+                    this._reconnectTimer = setTimeout(() => this._initWebSocket(), delay);
+                } else if (this._synced) {
+                    this._attemptReconnect();
+                } else {
+                    // Refused before it ever got going; find out whether the room exists at all
+                    this._checkRoom();
+                }
             };
 
             this._ws.onerror = (error) => {
@@ -223,42 +678,255 @@ class BoardStore {
         }
     }
 
+    private async _checkRoom(): Promise<void> {
+        const response = await fetch(`/api/${this._room}/board/${this._mode}`, {
+            headers: this._headers()
+        }).catch(() => null);
+        if (response && !response.ok) {
+            const body = await response.json().catch(() => null);
+            this._store.update((state) => ({
+                ...state,
+                loading: false,
+                error: body?.message ?? `HTTP ${response.status}: ${response.statusText}`
+            }));
+            return;
+        }
+        this._attemptReconnect();
+    }
+
     private _handleWebSocketMessage(message: WsMessage): void {
         switch (message.type) {
+            case "snapshot": {
+                const snapshot = message.data as Snapshot;
+                this._lastSeq = snapshot.seq;
+                this._resyncId = null;
+                this._store.update((state) => ({
+                    ...state,
+                    board: snapshot.key ?? snapshot.board,
+                    turn: snapshot.turn,
+                    gameOver: snapshot.game_over,
+                    history: snapshot.history,
+                    code: snapshot.code ?? null,
+                    seats: snapshot.seats,
+                    players: snapshot.players,
+                    loading: false,
+                    error: null,
+                    restarting: false
+                }));
+                break;
+            }
+
             case "card_revealed":
                 if ("row" in message.data) {
-                    const { row, col, new_card_state } = message.data;
+                    const { row, col, new_card_state: card } = message.data;
                     this._store.update((state) => {
                         const newBoard = [...state.board];
                         newBoard[row] = [...newBoard[row]];
-                        newBoard[row][col] = new_card_state;
-                        return { ...state, board: newBoard };
+                        newBoard[row][col] = card;
+
+                        const history = [...state.history];
+                        const current = history.pop();
+                        if (current) {
+                            const guess = { row, col, team: card.team };
+                            history.push({ ...current, guesses: [...current.guesses, guess] });
+                        }
+                        return {
+                            ...state,
+                            board: newBoard,
+                            history,
+                            canUndo: true,
+                            undoVotes: null
+                        };
                     });
                 }
                 break;
 
-            case "new_game":
+            case "card_hidden": {
+                const { game } = message.data as { row: number; col: number; game: GameData };
+                this._store.update((state) => ({
+                    ...state,
+                    board: game.key ?? game.board,
+                    turn: game.turn,
+                    gameOver: game.game_over,
+                    history: game.history,
+                    code: game.code ?? null,
+                    canUndo: false,
+                    undoVotes: null
+                }));
+                break;
+            }
+
+            case "undo_vote": {
+                const undoVotes = message.data as UndoVotes;
+                this._store.update((state) => ({ ...state, undoVotes }));
+                break;
+            }
+
+            case "new_game": {
+                const { board, turn, code } = message.data as GameData;
                 this._store.update((state) => ({
                     ...state,
-                    board: message.data as PublicBoard | SpymasterBoard,
+                    board,
+                    turn,
+                    gameOver: null,
+                    history: [],
+                    code: code ?? null,
+                    canUndo: false,
                     loading: false,
                     error: null
                 }));
                 break;
+            }
+
+            case "clue_given": {
+                const clue = message.data as Clue;
+                this._store.update((state) => ({
+                    ...state,
+                    history: [...state.history, { clue, guesses: [], passed: false }],
+                    canUndo: false
+                }));
+                break;
+            }
+
+            case "turn_changed":
+                if ("turn" in message.data) {
+                    const { turn } = message.data;
+                    this._store.update((state) => ({ ...state, turn }));
+                }
+                break;
+
+            case "turn_passed":
+                this._store.update((state) => {
+                    const history = [...state.history];
+                    const current = history.pop();
+                    if (current) {
+                        history.push({ ...current, passed: true });
+                    }
+                    return { ...state, history, canUndo: false };
+                });
+                break;
+
+            case "game_over":
+                if ("key" in message.data) {
+                    const { winner, reason, key } = message.data;
+                    this._store.update((state) => ({
+                        ...state,
+                        board: key,
+                        gameOver: { winner, reason }
+                    }));
+                }
+                break;
+
+            case "seats_changed": {
+                const seats = message.data as Seats;
+                this._store.update((state) => ({ ...state, seats }));
+                break;
+            }
+
+            case "player_joined":
+            case "player_changed_team":
+            case "player_connected":
+            case "player_disconnected": {
+                const player = message.data as Player;
+                this._store.update((state) => ({
+                    ...state,
+                    players: [...state.players.filter((p) => p.id !== player.id), player],
+                    myPlayer: state.myPlayer?.id === player.id ? player : state.myPlayer
+                }));
+                break;
+            }
+
+            case "player_left": {
+                const player = message.data as Player;
+                if (get(this._store).myPlayer?.id === player.id) {
+                    this._forgetSession();
+                }
+                this._store.update((state) => ({
+                    ...state,
+                    players: state.players.filter((p) => p.id !== player.id)
+                }));
+                break;
+            }
+
+            case "chat": {
+                const chatMessage = message.data as ChatMessage;
+                this._store.update((state) => ({
+                    ...state,
+                    chat: [...state.chat, chatMessage].slice(-100)
+                }));
+                break;
+            }
+
+            case "hello":
+                break;
+
+            case "ack": {
+                const { id } = message.data as { id: string | null };
+                if (id === this._resyncId) this._resyncId = null;
+                break;
+            }
+
+            case "error": {
+                const { id, message: errorMessage } = message.data as ErrorData;
+                if (id === this._resyncId) this._resyncId = null;
+                this._store.update((state) => ({ ...state, error: errorMessage }));
+                break;
+            }
+
+            case "room_expired":
+                this._expired = true;
+                this._store.update((state) => ({
+                    ...state,
+                    error: "This game expired after being left idle. Start a new game from the home page."
+                }));
+                break;
+
+            case "server_shutting_down": {
+                // Event streams are told when to reconnect by the server itself
+                const { reconnect_after_ms } = message.data as { reconnect_after_ms: number };
+                if (this._ws) this._restartDelay = reconnect_after_ms;
+                this._store.update((state) => ({ ...state, restarting: true }));
+                break;
+            }
 
             default:
                 console.warn("Unknown WebSocket message type:", message.type);
         }
     }
 
+    // Read-only fallback for networks where WebSockets don't get through; the browser reconnects
+    // by itself, resuming from the last event id
+    private _initEventSource(): void {
+        this._eventSource?.close();
+        this._eventSource = new EventSource(
+            `/sse/${this._room}/${this._mode}?${this._connectionParams()}`
+        );
+
+        this._eventSource.onopen = () => {
+            this._store.update((state) => ({ ...state, connected: true, error: null }));
+        };
+
+        this._eventSource.onmessage = (event) => {
+            try {
+                const message: WsMessage = JSON.parse(event.data);
+                if (message.seq !== undefined && !this._acceptSeq(message.seq)) return;
+                this._handleWebSocketMessage(message);
+            } catch (error) {
+                console.error("Failed to parse event:", error);
+            }
+        };
+
+        this._eventSource.onerror = () => {
+            this._store.update((state) => ({ ...state, connected: false }));
+        };
+    }
+
     private _attemptReconnect(): void {
         const currentState = get(this._store);
 
         if (currentState.reconnectAttempts >= this._maxReconnectAttempts) {
-            this._store.update((state) => ({
-                ...state,
-                error: "Unable to reconnect to server. Please refresh the page."
-            }));
+            console.log("WebSocket unavailable, falling back to Server-Sent Events");
+            this._initEventSource();
             return;
         }
 
@@ -281,6 +949,8 @@ class BoardStore {
         if (this._ws) {
             this._ws.close();
         }
+        this._eventSource?.close();
+        this._eventSource = null;
         if (this._reconnectTimer) {
             clearTimeout(this._reconnectTimer);
         }
@@ -1,67 +1,641 @@
-use crate::game::GameState;
-use crate::websocket::{CardRevealData, ClientType, WsMessage, WsState};
-use actix_web::{HttpResponse, Responder, web};
+use crate::game::{GameCode, GameError, KeyCard, Turn, Undo, UndoVotes};
+use crate::player::{MAX_CHAT_LENGTH, Player, PlayerError, PlayerId, PlayerSession, Role};
+use crate::room::{self, Room, RoomRegistry, SeatedSpymaster, SpymasterRoom};
+use crate::websocket::{
+    CardHiddenData, CardRevealData, ChatMessage, ClientType, EventLock, GameOverData,
+    TurnChangeData, TurnPassData, WsMessage,
+};
+use crate::words::{PackWeight, WordPackAdmin, WordPacks};
+use actix_web::{HttpRequest, HttpResponse, Responder, ResponseError, web};
 use serde::Deserialize;
 
-pub async fn get_board_public(game_state: web::Data<GameState>) -> impl Responder {
-    web::Json(game_state.public_json())
+/// An error reported to clients by a short title along with its full message
+pub trait ApiError: std::fmt::Display {
+    /// A few words naming the error, like "Not your turn", sent as the `error` field of API
+    /// responses and WebSocket replies
+    fn kind(&self) -> &'static str;
+
+    /// Anything more a client can act on, like which rule a clue broke
+    fn details(&self) -> Option<serde_json::Value> {
+        None
+    }
+
+    /// The `{error, message, details}` body sent for this error over HTTP and WebSockets alike
+    fn body(&self) -> serde_json::Value {
+        let mut body = serde_json::json!({
+            "error": self.kind(),
+            "message": self.to_string(),
+        });
+        if let Some(details) = self.details() {
+            body["details"] = details;
+        }
+        body
+    }
+}
+
+pub fn game_error(err: GameError) -> HttpResponse {
+    let mut response = match err {
+        GameError::InvalidCoordinates
+        | GameError::InvalidClue(_)
+        | GameError::InvalidClueNumber
+        | GameError::InvalidGameCode => HttpResponse::BadRequest(),
+        GameError::WordPack(pack_error) => return pack_error.error_response(),
+        GameError::AlreadyRevealed
+        | GameError::NotYourTurn { .. }
+        | GameError::GameOver
+        | GameError::NoClue
+        | GameError::ClueAlreadyGiven
+        | GameError::NothingToUndo
+        | GameError::UndoWindowClosed
+        | GameError::WordListMismatch { .. } => HttpResponse::Conflict(),
+    };
+    response.json(err.body())
+}
+
+pub async fn post_room(rooms: web::Data<RoomRegistry>) -> impl Responder {
+    let room = match rooms.create() {
+        Ok(room) => room,
+        Err(err) => return err.error_response(),
+    };
+    log::info!("Created room {}", room.code);
+    HttpResponse::Ok().json(serde_json::json!({
+        "code": room.code,
+        "spymaster_token": room.spymaster_token,
+    }))
+}
+
+/// The player whose session token came with the request, if one did
+pub fn session_player(req: &HttpRequest, room: &Room) -> Result<Option<PlayerId>, PlayerError> {
+    room::session_token(req)
+        .map(|token| room.players.session(&token))
+        .transpose()
+}
+
+/// Deals a key card for a random board, with either team going first
+pub async fn post_key_card(word_packs: web::Data<WordPacks>) -> impl Responder {
+    let first_turn = if rand::random() {
+        Turn::Blue
+    } else {
+        Turn::Red
+    };
+    let code = GameCode::random(first_turn, word_packs.default_source().version());
+    web::Json(KeyCard::new(code))
+}
+
+/// The key card for a game code. The code's word list version doesn't matter, since the key is
+/// dealt without any words.
+pub async fn get_key_card(code: web::Path<String>) -> impl Responder {
+    match code.parse::<GameCode>() {
+        Ok(code) => HttpResponse::Ok().json(KeyCard::new(code)),
+        Err(err) => game_error(err),
+    }
+}
+
+/// The word packs games can be dealt from, with how many words each has
+pub async fn get_word_packs(word_packs: web::Data<WordPacks>) -> impl Responder {
+    web::Json(word_packs.summaries())
+}
+
+pub async fn get_word_pack(
+    name: web::Path<String>,
+    word_packs: web::Data<WordPacks>,
+) -> impl Responder {
+    match word_packs.get(&name) {
+        Ok(pack) => HttpResponse::Ok().json(serde_json::json!({
+            "name": pack.name,
+            "version": pack.version,
+            "words": pack.words,
+            "uploaded": pack.uploaded,
+        })),
+        Err(err) => err.error_response(),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WordPackParams {
+    pub name: String,
+    pub words: Vec<String>,
+}
+
+pub async fn post_word_pack(
+    req: web::Json<WordPackParams>,
+    WordPackAdmin(word_packs): WordPackAdmin,
+) -> impl Responder {
+    let WordPackParams { name, words } = req.into_inner();
+    match word_packs.create(name, words) {
+        Ok(summary) => {
+            log::info!("Uploaded word pack {}", summary.name);
+            HttpResponse::Created().json(summary)
+        }
+        Err(err) => err.error_response(),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WordPackWordsParams {
+    pub words: Vec<String>,
+}
+
+pub async fn put_word_pack(
+    name: web::Path<String>,
+    req: web::Json<WordPackWordsParams>,
+    WordPackAdmin(word_packs): WordPackAdmin,
+) -> impl Responder {
+    match word_packs.update(&name, req.into_inner().words) {
+        Ok(summary) => HttpResponse::Ok().json(summary),
+        Err(err) => err.error_response(),
+    }
+}
+
+pub async fn delete_word_pack(
+    name: web::Path<String>,
+    WordPackAdmin(word_packs): WordPackAdmin,
+) -> impl Responder {
+    match word_packs.delete(&name) {
+        Ok(()) => HttpResponse::NoContent().finish(),
+        Err(err) => err.error_response(),
+    }
+}
+
+pub async fn get_board_public(room: Room) -> impl Responder {
+    web::Json(room.game.public_json())
+}
+
+pub async fn get_board_spymaster(SpymasterRoom(room): SpymasterRoom) -> impl Responder {
+    web::Json(room.game.spymaster_json())
 }
 
-pub async fn get_board_spymaster(game_state: web::Data<GameState>) -> impl Responder {
-    web::Json(game_state.spymaster_json())
+/// The room's full event log, from which every game it has hosted can be replayed.
+///
+/// Each new board reveals its key, so the log is only available to spymasters.
+pub async fn get_events(SpymasterRoom(room): SpymasterRoom) -> impl Responder {
+    web::Json(room.game.events())
 }
 
 #[derive(Debug, Deserialize)]
 pub struct RevealParams {
     pub row: usize,
     pub col: usize,
+    pub team: Turn,
 }
 
 pub async fn post_reveal(
+    http_req: HttpRequest,
     req: web::Json<RevealParams>,
-    game_state: web::Data<GameState>,
-    ws_state: web::Data<WsState>,
+    room: Room,
 ) -> impl Responder {
-    let (row, col) = (req.row, req.col);
-    if row >= 5 || col >= 5 {
-        return HttpResponse::BadRequest().json(serde_json::json!({
-            "error": "Invalid coordinates",
-            "message": "Row and column must be between 0 and 4."
-        }));
+    let player = match session_player(&http_req, &room) {
+        Ok(player) => player,
+        Err(err) => return err.error_response(),
+    };
+    if let Err(err) = room.players.check_operative(player, req.team) {
+        return err.error_response();
     }
 
-    ws_state.broadcast((
+    match reveal(&req, player, &room) {
+        Ok(()) => HttpResponse::Ok().finish(),
+        Err(err) => game_error(err),
+    }
+}
+
+/// Reveals a card for `player`, whose team must already have been checked, and announces the
+/// outcome
+pub fn reveal(
+    params: &RevealParams,
+    player: Option<PlayerId>,
+    room: &Room,
+) -> Result<(), GameError> {
+    let (row, col) = (params.row, params.col);
+    let mut events = room.ws.lock_events();
+    let reveal = room.game.reveal_card(row, col, params.team, player)?;
+
+    events.broadcast((
         WsMessage::CardRevealed {
             data: CardRevealData {
                 row,
                 col,
-                new_card_state: serde_json::json!(game_state.reveal_card(row, col)),
+                new_card_state: reveal.card,
             },
         },
         None,
     ));
 
-    HttpResponse::Ok().into()
+    if let Some(turn) = reveal.turn_changed {
+        events.broadcast((
+            WsMessage::TurnChanged {
+                data: TurnChangeData { turn },
+            },
+            None,
+        ));
+    }
+
+    if let Some(game_over) = reveal.game_over {
+        events.broadcast((
+            WsMessage::GameOver {
+                data: GameOverData {
+                    winner: game_over.winner,
+                    reason: game_over.reason,
+                    key: room.game.key_json(),
+                },
+            },
+            None,
+        ));
+    }
+
+    Ok(())
 }
 
-pub async fn post_new_game(
-    game_state: web::Data<GameState>,
-    ws_state: web::Data<WsState>,
+pub async fn post_undo(SpymasterRoom(room): SpymasterRoom) -> impl Responder {
+    match undo(&room) {
+        Ok(()) => HttpResponse::Ok().finish(),
+        Err(err) => game_error(err),
+    }
+}
+
+/// Undoes the last reveal on the host's say-so and announces it
+pub fn undo(room: &Room) -> Result<(), GameError> {
+    let mut events = room.ws.lock_events();
+    let undo = room.game.undo_reveal()?;
+    announce_undo(undo, room, &mut events);
+    Ok(())
+}
+
+pub async fn post_vote_undo(PlayerSession { room, player }: PlayerSession) -> impl Responder {
+    match vote_undo(player, &room) {
+        Ok(votes) => HttpResponse::Ok().json(votes),
+        Err(err) => game_error(err),
+    }
+}
+
+/// Adds `player`'s vote to undo the last reveal, undoing it once a majority of the roster agrees
+pub fn vote_undo(player: PlayerId, room: &Room) -> Result<UndoVotes, GameError> {
+    let electorate = room.players.players().len();
+    let mut events = room.ws.lock_events();
+    let (votes, undo) = room.game.vote_undo(player, electorate)?;
+    events.broadcast((WsMessage::UndoVote { data: votes }, None));
+    if let Some(undo) = undo {
+        announce_undo(undo, room, &mut events);
+    }
+    Ok(votes)
+}
+
+/// Sends each client the hidden card along with its corrected view of the game, since the turn,
+/// the clue log and any game over may all have changed
+fn announce_undo(undo: Undo, room: &Room, events: &mut EventLock) {
+    events.broadcast_event(vec![
+        (
+            WsMessage::CardHidden {
+                data: CardHiddenData {
+                    row: undo.row,
+                    col: undo.col,
+                    game: room.game.public_json(),
+                },
+            },
+            Some(ClientType::Public),
+        ),
+        (
+            WsMessage::CardHidden {
+                data: CardHiddenData {
+                    row: undo.row,
+                    col: undo.col,
+                    game: room.game.spymaster_json(),
+                },
+            },
+            Some(ClientType::Spymaster),
+        ),
+    ]);
+}
+
+pub async fn get_seats(room: Room) -> impl Responder {
+    web::Json(room.seats_json())
+}
+
+pub async fn post_claim_seat(
+    req: HttpRequest,
+    path: web::Path<(String, Turn)>,
+    SpymasterRoom(room): SpymasterRoom,
 ) -> impl Responder {
-    game_state.new_game();
+    let (_, team) = path.into_inner();
+    // Tie the seat to the caller's player session, if any, so it survives a reconnect
+    let player = match session_player(&req, &room) {
+        Ok(player) => player,
+        Err(err) => return err.error_response(),
+    };
+
+    // Once players have joined, seats go only to their team's spymasters
+    if let Err(err) = room.players.check_spymaster(player, team) {
+        return err.error_response();
+    }
+
+    let mut events = room.ws.lock_events();
+    let held = room.request_seat_token(&req, player);
+    let seat_token = match room.claim_seat(team, held.as_deref()) {
+        Ok(seat_token) => seat_token,
+        Err(err) => return err.error_response(),
+    };
+    if let Some(player) = player {
+        room.players
+            .set_seat_token(player, Some(seat_token.clone()));
+    }
 
-    ws_state.broadcast((
-        WsMessage::NewGame {
-            data: game_state.public_json(),
+    events.broadcast((
+        WsMessage::SeatsChanged {
+            data: room.seats_json(),
         },
-        Some(ClientType::Public),
+        None,
     ));
-    ws_state.broadcast((
-        WsMessage::NewGame {
-            data: game_state.spymaster_json(),
+
+    HttpResponse::Ok().json(serde_json::json!({
+        "team": team,
+        "seat_token": seat_token,
+    }))
+}
+
+pub async fn post_release_seat(
+    req: HttpRequest,
+    path: web::Path<(String, Turn)>,
+    room: Room,
+) -> impl Responder {
+    let (_, team) = path.into_inner();
+    let mut events = room.ws.lock_events();
+    if let Err(err) = room.release_seat(team, &req) {
+        return err.error_response();
+    }
+
+    events.broadcast((
+        WsMessage::SeatsChanged {
+            data: room.seats_json(),
         },
-        Some(ClientType::Spymaster),
+        None,
     ));
 
     HttpResponse::Ok().finish()
 }
+
+pub async fn get_players(room: Room) -> impl Responder {
+    web::Json(room.players.players())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct JoinParams {
+    pub nickname: String,
+    pub team: Turn,
+    pub role: Role,
+}
+
+/// Adds a player to the roster and announces them, returning them along with their session
+/// token
+pub fn join(params: &JoinParams, room: &Room) -> Result<(Player, String), PlayerError> {
+    let mut events = room.ws.lock_events();
+    let (player, session_token) = room
+        .players
+        .join(&params.nickname, params.team, params.role)?;
+    events.broadcast((
+        WsMessage::PlayerJoined {
+            data: player.clone(),
+        },
+        None,
+    ));
+    Ok((player, session_token))
+}
+
+pub async fn post_join(req: web::Json<JoinParams>, room: Room) -> impl Responder {
+    match join(&req, &room) {
+        Ok((player, session_token)) => HttpResponse::Ok().json(serde_json::json!({
+            "player": player,
+            "session_token": session_token,
+        })),
+        Err(err) => err.error_response(),
+    }
+}
+
+/// Resumes a session: the caller's player, and their spymaster seat if they still hold it
+pub async fn get_session(session: PlayerSession) -> impl Responder {
+    let PlayerSession { room, player } = session;
+    let player = match room.players.get(player) {
+        Ok(player) => player,
+        Err(err) => return err.error_response(),
+    };
+    let seat = room.players.seat_token(player.id).and_then(|seat_token| {
+        room.seat_team(&seat_token).map(|team| {
+            serde_json::json!({
+                "team": team,
+                "seat_token": seat_token,
+            })
+        })
+    });
+
+    HttpResponse::Ok().json(serde_json::json!({
+        "player": player,
+        "seat": seat,
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChangeTeamParams {
+    pub team: Turn,
+    pub role: Role,
+}
+
+pub async fn post_change_team(
+    req: web::Json<ChangeTeamParams>,
+    session: PlayerSession,
+) -> impl Responder {
+    let PlayerSession { room, player } = session;
+    let mut events = room.ws.lock_events();
+    let player = match room.players.change_team(player, req.team, req.role) {
+        Ok(player) => player,
+        Err(err) => return err.error_response(),
+    };
+
+    events.broadcast((
+        WsMessage::PlayerChangedTeam {
+            data: player.clone(),
+        },
+        None,
+    ));
+
+    HttpResponse::Ok().json(player)
+}
+
+/// Removes a player from the roster, frees any seat they held and announces it
+pub fn leave(id: PlayerId, room: &Room) -> Result<(), PlayerError> {
+    let mut events = room.ws.lock_events();
+    let (player, seat_token) = room.players.leave(id)?;
+    events.broadcast((WsMessage::PlayerLeft { data: player }, None));
+
+    if let Some(seat_token) = seat_token
+        && room.vacate_seat(&seat_token)
+    {
+        events.broadcast((
+            WsMessage::SeatsChanged {
+                data: room.seats_json(),
+            },
+            None,
+        ));
+    }
+    Ok(())
+}
+
+pub async fn post_leave(PlayerSession { room, player }: PlayerSession) -> impl Responder {
+    match leave(player, &room) {
+        Ok(()) => HttpResponse::Ok().finish(),
+        Err(err) => err.error_response(),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChatParams {
+    pub text: String,
+}
+
+/// Sends a chat message from `player` to everyone in the room
+pub fn chat(player: PlayerId, params: &ChatParams, room: &Room) -> Result<(), PlayerError> {
+    let player = room.players.get(player)?;
+    let text = params.text.trim();
+    if text.is_empty() || text.chars().count() > MAX_CHAT_LENGTH {
+        return Err(PlayerError::InvalidChatMessage);
+    }
+
+    room.ws.broadcast((
+        WsMessage::Chat {
+            data: ChatMessage {
+                player,
+                text: text.to_string(),
+            },
+        },
+        None,
+    ));
+    Ok(())
+}
+
+pub async fn post_chat(
+    req: web::Json<ChatParams>,
+    PlayerSession { room, player }: PlayerSession,
+) -> impl Responder {
+    match chat(player, &req, &room) {
+        Ok(()) => HttpResponse::Ok().finish(),
+        Err(err) => err.error_response(),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ClueParams {
+    pub word: String,
+    pub number: u8,
+}
+
+/// Records `team`'s clue and announces it to every client
+pub fn give_clue(team: Turn, params: &ClueParams, room: &Room) -> Result<(), GameError> {
+    let mut events = room.ws.lock_events();
+    let clue = room.game.give_clue(team, &params.word, params.number)?;
+    events.broadcast((WsMessage::ClueGiven { data: clue }, None));
+    Ok(())
+}
+
+pub async fn post_clue(req: web::Json<ClueParams>, seat: SeatedSpymaster) -> impl Responder {
+    match give_clue(seat.team, &req, &seat.room) {
+        Ok(()) => HttpResponse::Ok().finish(),
+        Err(err) => game_error(err),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EndTurnParams {
+    pub team: Turn,
+}
+
+/// Passes the turn to the other team and announces it
+pub fn end_turn(params: &EndTurnParams, room: &Room) -> Result<(), GameError> {
+    let mut events = room.ws.lock_events();
+    let turn = room.game.end_turn(params.team)?;
+    events.broadcast((
+        WsMessage::TurnPassed {
+            data: TurnPassData { team: params.team },
+        },
+        None,
+    ));
+    events.broadcast((
+        WsMessage::TurnChanged {
+            data: TurnChangeData { turn },
+        },
+        None,
+    ));
+    Ok(())
+}
+
+pub async fn post_end_turn(
+    http_req: HttpRequest,
+    req: web::Json<EndTurnParams>,
+    room: Room,
+) -> impl Responder {
+    let player = match session_player(&http_req, &room) {
+        Ok(player) => player,
+        Err(err) => return err.error_response(),
+    };
+    if let Err(err) = room.players.check_operative(player, req.team) {
+        return err.error_response();
+    }
+
+    match end_turn(&req, &room) {
+        Ok(()) => HttpResponse::Ok().finish(),
+        Err(err) => game_error(err),
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct NewGameParams {
+    /// Replays the board with this game code instead of dealing a random one
+    pub code: Option<String>,
+    /// The word packs to deal from; the room's default packs if empty
+    #[serde(default)]
+    pub packs: Vec<PackWeight>,
+}
+
+pub async fn post_new_game(
+    SpymasterRoom(room): SpymasterRoom,
+    // The body is optional, so a bare POST still deals a random board
+    params: Option<web::Json<NewGameParams>>,
+) -> impl Responder {
+    let params = params.map(web::Json::into_inner).unwrap_or_default();
+    match new_game(&params, &room) {
+        Ok(code) => HttpResponse::Ok().json(serde_json::json!({ "code": code })),
+        Err(err) => game_error(err),
+    }
+}
+
+/// Deals a new board and sends each client its view of it, returning the board's code
+pub fn new_game(params: &NewGameParams, room: &Room) -> Result<GameCode, GameError> {
+    let code = params
+        .code
+        .as_deref()
+        .map(str::parse::<GameCode>)
+        .transpose()?;
+    // A code from a single pack is replayed from that pack whatever was chosen; codes from a mix of
+    // packs need the same mix chosen again
+    let words = match code.and_then(|code| room.word_packs.with_version(code.words_version)) {
+        Some(words) => words,
+        None => room
+            .word_packs
+            .select(&params.packs)
+            .map_err(GameError::WordPack)?,
+    };
+    let mut events = room.ws.lock_events();
+    let code = room.game.new_game(code, &words)?;
+
+    events.broadcast_event(vec![
+        (
+            WsMessage::NewGame {
+                data: room.game.public_json(),
+            },
+            Some(ClientType::Public),
+        ),
+        (
+            WsMessage::NewGame {
+                data: room.game.spymaster_json(),
+            },
+            Some(ClientType::Spymaster),
+        ),
+    ]);
+    Ok(code)
+}
@@ -1,11 +1,18 @@
-use rand::rng;
 use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng, rng};
+use rand_chacha::ChaCha8Rng;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::str::FromStr;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use crate::words::get_words;
+use crate::api::ApiError;
+use crate::player::PlayerId;
+use crate::validation::{ClueError, validate_clue};
+use crate::words::{WordPackError, WordSource};
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum Team {
     Blue,
@@ -37,25 +44,648 @@ impl Card {
     }
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum Turn {
     Blue,
     Red,
 }
 
+impl Turn {
+    pub fn other(self) -> Self {
+        match self {
+            Turn::Blue => Turn::Red,
+            Turn::Red => Turn::Blue,
+        }
+    }
+}
+
+impl From<Turn> for Team {
+    fn from(turn: Turn) -> Self {
+        match turn {
+            Turn::Blue => Team::Blue,
+            Turn::Red => Team::Red,
+        }
+    }
+}
+
 pub type Board = [[Card; 5]; 5];
+/// The team of each card on a board
+pub type Key = [[Team; 5]; 5];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GameOverReason {
+    /// The winning team revealed all of its own cards
+    AllAgentsFound,
+    /// The losing team revealed the assassin
+    Assassin,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GameOver {
+    pub winner: Turn,
+    pub reason: GameOverReason,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Clue {
+    pub team: Turn,
+    pub word: String,
+    pub number: u8,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Guess {
+    pub row: usize,
+    pub col: usize,
+    pub team: Team,
+    /// The player who revealed the card, if they had joined the game
+    pub player: Option<PlayerId>,
+}
+
+/// A single team's turn: the spymaster's clue and the operatives' guesses
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TurnRecord {
+    pub clue: Clue,
+    pub guesses: Vec<Guess>,
+    /// Whether the operatives chose to stop guessing
+    #[serde(default)]
+    pub passed: bool,
+}
+
+impl TurnRecord {
+    /// Operatives may make one more guess than the number given with the clue
+    pub fn guesses_remaining(&self) -> usize {
+        (self.clue.number as usize + 1).saturating_sub(self.guesses.len())
+    }
+}
+
+/// Digits of a game code's seed: Crockford's base 32, which leaves out the easily confused I, L, O
+/// and U
+const SEED_ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+/// Enough digits for a 64-bit seed
+const SEED_LENGTH: usize = 13;
+
+/// A short code that determines a board: its words, its key, and the team that goes first.
+///
+/// Written as the word list version and the seed, e.g. `1-9K3M9QA0D2XHT`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub struct GameCode {
+    /// The version of the word list the words are drawn from
+    pub words_version: u32,
+    /// Seeds the shuffles that deal the board; its lowest bit picks the team that goes first.
+    ///
+    /// The words on a board are public, so the seed must be too long to find by dealing every
+    /// possible board and comparing words: that would give away the key mid-game.
+    pub seed: u64,
+}
+
+impl GameCode {
+    /// A random code for a board that `first_turn` starts, dealt from words with `words_version`
+    pub fn random(first_turn: Turn, words_version: u32) -> Self {
+        let team_bit = match first_turn {
+            Turn::Blue => 0,
+            Turn::Red => 1,
+        };
+        Self {
+            words_version,
+            seed: (rng().random::<u64>() & !1) | team_bit,
+        }
+    }
+
+    pub fn first_turn(self) -> Turn {
+        if self.seed & 1 == 0 {
+            Turn::Blue
+        } else {
+            Turn::Red
+        }
+    }
+}
+
+impl std::fmt::Display for GameCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}-", self.words_version)?;
+        for digit in (0..SEED_LENGTH).rev() {
+            let value = (self.seed >> (digit * 5)) & 31;
+            write!(f, "{}", SEED_ALPHABET[value as usize] as char)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for GameCode {
+    type Err = GameError;
+
+    /// Parses a code case-insensitively
+    fn from_str(code: &str) -> Result<Self, Self::Err> {
+        let (version, seed) = code
+            .trim()
+            .split_once('-')
+            .ok_or(GameError::InvalidGameCode)?;
+        let words_version = version.parse().map_err(|_| GameError::InvalidGameCode)?;
+        if seed.len() != SEED_LENGTH {
+            return Err(GameError::InvalidGameCode);
+        }
+
+        // 13 digits hold 65 bits, so a seed can overflow `u64` by one bit
+        let seed = seed.bytes().try_fold(0u128, |seed, digit| {
+            let value = SEED_ALPHABET
+                .iter()
+                .position(|&allowed| allowed == digit.to_ascii_uppercase())
+                .ok_or(GameError::InvalidGameCode)?;
+            Ok(seed << 5 | value as u128)
+        })?;
+        Ok(Self {
+            words_version,
+            seed: seed.try_into().map_err(|_| GameError::InvalidGameCode)?,
+        })
+    }
+}
+
+impl From<GameCode> for String {
+    fn from(code: GameCode) -> Self {
+        code.to_string()
+    }
+}
+
+impl TryFrom<String> for GameCode {
+    type Error = GameError;
+
+    fn try_from(code: String) -> Result<Self, Self::Error> {
+        code.parse()
+    }
+}
+
+/// A spymaster key on its own, for groups who lay out a physical deck of word cards
+#[derive(Debug, Clone, Serialize)]
+pub struct KeyCard {
+    pub code: GameCode,
+    pub first_turn: Turn,
+    pub key: Key,
+}
+
+impl KeyCard {
+    pub fn new(code: GameCode) -> Self {
+        Self {
+            code,
+            first_turn: code.first_turn(),
+            key: GameState::new_key(code),
+        }
+    }
+}
+
+/// The largest number a spymaster may give with a clue
+pub const MAX_CLUE_NUMBER: u8 = 9;
+pub const DEFAULT_UNDO_WINDOW: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GameError {
+    InvalidCoordinates,
+    AlreadyRevealed,
+    NotYourTurn { current: Turn },
+    GameOver,
+    NoClue,
+    ClueAlreadyGiven,
+    InvalidClue(ClueError),
+    InvalidClueNumber,
+    NothingToUndo,
+    UndoWindowClosed,
+    InvalidGameCode,
+    WordListMismatch { version: u32, current: u32 },
+    WordPack(WordPackError),
+}
+
+impl ApiError for GameError {
+    fn kind(&self) -> &'static str {
+        match self {
+            GameError::InvalidCoordinates => "Invalid coordinates",
+            GameError::AlreadyRevealed => "Already revealed",
+            GameError::NotYourTurn { .. } => "Not your turn",
+            GameError::GameOver => "Game over",
+            GameError::NoClue => "No clue",
+            GameError::ClueAlreadyGiven => "Clue already given",
+            GameError::InvalidClue(_) => "Invalid clue",
+            GameError::InvalidClueNumber => "Invalid clue number",
+            GameError::NothingToUndo => "Nothing to undo",
+            GameError::UndoWindowClosed => "Undo window closed",
+            GameError::InvalidGameCode => "Invalid game code",
+            GameError::WordListMismatch { .. } => "Word list mismatch",
+            GameError::WordPack(err) => err.kind(),
+        }
+    }
+
+    fn details(&self) -> Option<serde_json::Value> {
+        match self {
+            GameError::InvalidClue(clue_error) => Some(serde_json::json!(clue_error)),
+            GameError::WordPack(err) => err.details(),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for GameError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GameError::InvalidCoordinates => {
+                write!(f, "Row and column must be between 0 and 4.")
+            }
+            GameError::AlreadyRevealed => write!(f, "That card has already been revealed."),
+            GameError::NotYourTurn { current } => {
+                write!(f, "It is currently {current:?} team's turn.")
+            }
+            GameError::GameOver => write!(f, "The game is over; start a new game to keep playing."),
+            GameError::NoClue => write!(f, "The spymaster hasn't given a clue yet this turn."),
+            GameError::ClueAlreadyGiven => {
+                write!(f, "A clue has already been given this turn.")
+            }
+            GameError::InvalidClue(err) => write!(f, "{err}"),
+            GameError::InvalidClueNumber => {
+                write!(
+                    f,
+                    "The clue number must be between 0 and {MAX_CLUE_NUMBER}."
+                )
+            }
+            GameError::NothingToUndo => {
+                write!(
+                    f,
+                    "Only the most recent action can be undone, and it wasn't a reveal."
+                )
+            }
+            GameError::UndoWindowClosed => {
+                write!(f, "It's too late to undo the last reveal.")
+            }
+            GameError::InvalidGameCode => {
+                write!(
+                    f,
+                    "Game codes look like 1-9K3M9QA0D2XHT: a version, a dash and a seed."
+                )
+            }
+            GameError::WordListMismatch { version, current } => write!(
+                f,
+                "That code was made with version {version} of the word list, \
+                 but the chosen words have version {current}."
+            ),
+            GameError::WordPack(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl std::error::Error for GameError {}
+
+/// The result of a successful reveal
+#[derive(Debug, Clone)]
+pub struct Reveal {
+    pub card: Card,
+    /// The team whose turn it now is, if the reveal ended the current turn
+    pub turn_changed: Option<Turn>,
+    /// Set if the reveal ended the game
+    pub game_over: Option<GameOver>,
+}
+
+/// The position of a card whose reveal was undone
+#[derive(Debug, Clone, Copy)]
+pub struct Undo {
+    pub row: usize,
+    pub col: usize,
+}
+
+/// How far a vote to undo the last reveal has got
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct UndoVotes {
+    pub votes: usize,
+    pub needed: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct GameConfig {
+    /// Only refuse clues that exactly match an unrevealed card
+    pub lenient_clues: bool,
+    /// How long after a reveal it may still be undone
+    pub undo_window: Duration,
+}
+
+impl Default for GameConfig {
+    fn default() -> Self {
+        Self {
+            lenient_clues: false,
+            undo_window: DEFAULT_UNDO_WINDOW,
+        }
+    }
+}
+
+/// Something that happened in a game.
+///
+/// The game's state is never changed directly: every action appends events to the game's log,
+/// and the board, turn, clue history and outcome are all rebuilt by replaying them in order.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum GameEvent {
+    /// A fresh board was dealt, starting a new game
+    BoardCreated {
+        board: Box<Board>,
+        first_turn: Turn,
+        /// The code the board was dealt from
+        code: GameCode,
+    },
+    ClueGiven {
+        clue: Clue,
+    },
+    CardRevealed {
+        row: usize,
+        col: usize,
+        team: Team,
+        player: Option<PlayerId>,
+    },
+    /// `team`'s turn ended, either because its operatives chose to stop guessing or as a result of
+    /// a reveal
+    TurnEnded {
+        team: Turn,
+        passed: bool,
+    },
+    GameOver(GameOver),
+    /// The latest reveal was taken back, along with any turn change or game over it caused
+    RevealUndone {
+        row: usize,
+        col: usize,
+    },
+}
+
+impl GameEvent {
+    /// Whether the event only follows from the action before it, rather than being an action in
+    /// its own right
+    fn is_consequence(&self) -> bool {
+        matches!(
+            self,
+            GameEvent::TurnEnded { passed: false, .. } | GameEvent::GameOver(_)
+        )
+    }
+}
+
+/// A game event along with when it happened
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedEvent {
+    /// Milliseconds since the Unix epoch
+    pub at: u64,
+    #[serde(flatten)]
+    pub event: GameEvent,
+}
+
+/// The state of a game, as rebuilt from its events
+#[derive(Debug, Clone)]
+pub struct Game {
+    pub board: Board,
+    pub code: GameCode,
+    pub first_turn: Turn,
+    pub turn: Turn,
+    pub game_over: Option<GameOver>,
+    pub history: Vec<TurnRecord>,
+}
+
+impl Game {
+    /// Replays an event log, returning the state of the latest game in it, or `None` if no board
+    /// has been created yet
+    pub fn replay(events: &[RecordedEvent]) -> Option<Self> {
+        // Drop each undone reveal, along with whatever it caused, before applying anything
+        let mut effective: Vec<&GameEvent> = Vec::with_capacity(events.len());
+        for recorded in events {
+            if let GameEvent::RevealUndone { .. } = recorded.event {
+                while let Some(event) = effective.pop() {
+                    if let GameEvent::CardRevealed { .. } = event {
+                        break;
+                    }
+                }
+            } else {
+                effective.push(&recorded.event);
+            }
+        }
+
+        let mut game: Option<Self> = None;
+        for event in effective {
+            match (event, game.as_mut()) {
+                (
+                    GameEvent::BoardCreated {
+                        board,
+                        first_turn,
+                        code,
+                    },
+                    _,
+                ) => {
+                    game = Some(Self {
+                        board: (**board).clone(),
+                        code: *code,
+                        first_turn: *first_turn,
+                        turn: *first_turn,
+                        game_over: None,
+                        history: Vec::new(),
+                    });
+                }
+                (event, Some(game)) => game.apply(event),
+                (_, None) => {}
+            }
+        }
+        game
+    }
+
+    fn apply(&mut self, event: &GameEvent) {
+        match event {
+            GameEvent::BoardCreated {
+                board,
+                first_turn,
+                code,
+            } => {
+                self.board = (**board).clone();
+                self.code = *code;
+                self.first_turn = *first_turn;
+                self.turn = *first_turn;
+                self.game_over = None;
+                self.history.clear();
+            }
+            GameEvent::ClueGiven { clue } => self.history.push(TurnRecord {
+                clue: clue.clone(),
+                guesses: Vec::new(),
+                passed: false,
+            }),
+            GameEvent::CardRevealed {
+                row,
+                col,
+                team,
+                player,
+            } => {
+                self.board[*row][*col].revealed = true;
+                if let Some(record) = self.history.last_mut() {
+                    record.guesses.push(Guess {
+                        row: *row,
+                        col: *col,
+                        team: *team,
+                        player: *player,
+                    });
+                }
+            }
+            GameEvent::TurnEnded { team, passed } => {
+                if *passed && let Some(record) = self.history.last_mut() {
+                    record.passed = true;
+                }
+                self.turn = team.other();
+            }
+            GameEvent::GameOver(game_over) => self.game_over = Some(*game_over),
+            // `replay` removes undone reveals before applying anything
+            GameEvent::RevealUndone { .. } => {}
+        }
+    }
+
+    /// The record of the current turn, if its clue has already been given.
+    ///
+    /// Turns alternate, so the last record belongs to the current turn exactly when its team matches.
+    fn current_record(&self) -> Option<&TurnRecord> {
+        self.history
+            .last()
+            .filter(|record| record.clue.team == self.turn)
+    }
+
+    /// The number of guesses the current team has left, or `None` if no clue has been given yet
+    pub fn guesses_remaining(&self) -> Option<usize> {
+        self.current_record().map(TurnRecord::guesses_remaining)
+    }
+
+    /// The full board, including the team of every card
+    pub fn key_json(&self) -> serde_json::Value {
+        serde_json::json!(self.board)
+    }
+
+    pub fn public_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "board": self
+                .board
+                .iter()
+                .map(|row| row.iter().map(Card::public_json).collect::<Vec<_>>())
+                .collect::<Vec<_>>(),
+            "turn": self.turn,
+            "game_over": self.game_over,
+            "key": self.game_over.map(|_| self.key_json()),
+            // The code gives away the key too
+            "code": self.game_over.map(|_| self.code),
+            "history": self.history,
+            "guesses_remaining": self.guesses_remaining(),
+        })
+    }
+
+    pub fn spymaster_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "board": self.key_json(),
+            "code": self.code,
+            "turn": self.turn,
+            "game_over": self.game_over,
+            "history": self.history,
+            "guesses_remaining": self.guesses_remaining(),
+        })
+    }
+
+    fn all_found(&self, team: Team) -> bool {
+        self.board
+            .iter()
+            .flatten()
+            .filter(|card| card.team == team)
+            .all(|card| card.revealed)
+    }
+}
+
+// A game's event log, kept alongside the state it replays to
+#[derive(Debug)]
+struct Engine {
+    events: Vec<RecordedEvent>,
+    game: Game,
+    // Players who have voted to undo the latest reveal
+    undo_votes: BTreeSet<PlayerId>,
+}
+
+impl Engine {
+    fn new(code: GameCode, words: &WordSource) -> Self {
+        let event = RecordedEvent {
+            at: now_millis(),
+            event: GameState::deal(code, words),
+        };
+        let events = vec![event];
+        Self {
+            // The log starts with a board, so it always replays to a game
+            game: Game::replay(&events).unwrap(),
+            events,
+            undo_votes: BTreeSet::new(),
+        }
+    }
+
+    fn record(&mut self, event: GameEvent) {
+        let undone = matches!(event, GameEvent::RevealUndone { .. });
+        if !undone {
+            self.game.apply(&event);
+        }
+        self.events.push(RecordedEvent {
+            at: now_millis(),
+            event,
+        });
+        if undone {
+            // Undoing can't be applied on top of the current state, so rebuild it from scratch
+            self.game = Game::replay(&self.events).unwrap();
+        }
+        self.undo_votes.clear();
+    }
+
+    /// The position and time of the latest action, if it was a reveal
+    fn last_reveal(&self) -> Option<(usize, usize, u64)> {
+        let action = self
+            .events
+            .iter()
+            .rev()
+            .find(|recorded| !recorded.event.is_consequence())?;
+        match action.event {
+            GameEvent::CardRevealed { row, col, .. } => Some((row, col, action.at)),
+            _ => None,
+        }
+    }
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u64)
+}
 
 #[derive(Debug, Clone)]
 pub struct GameState {
-    pub board: Arc<Mutex<Board>>,
-    pub first_turn: Arc<Mutex<Turn>>,
+    pub config: GameConfig,
+    engine: Arc<Mutex<Engine>>,
 }
 
 impl GameState {
-    pub fn new_board(first_turn: Turn) -> Board {
-        let mut rng = rng();
+    /// Deals the board that `code` determines from `words`, which should have the code's word list
+    /// version, since the same seed picks different words from different words.
+    pub fn new_board(code: GameCode, words: &WordSource) -> Board {
+        let mut rng = Self::board_rng(code);
+        let key = Self::deal_key(code.first_turn(), &mut rng);
+
+        let mut words = words.deal(&mut rng).into_iter();
+        key.map(|row| {
+            row.map(|team| Card {
+                word: words.next().unwrap(),
+                team,
+                revealed: false,
+            })
+        })
+    }
+
+    /// Deals just the key that `code` determines, which is the key of the board it deals too:
+    /// the teams are dealt before the words, so the word list plays no part
+    pub fn new_key(code: GameCode) -> Key {
+        Self::deal_key(code.first_turn(), &mut Self::board_rng(code))
+    }
 
+    fn board_rng(code: GameCode) -> ChaCha8Rng {
+        // Unlike `StdRng`, ChaCha8 is guaranteed to produce the same numbers in every release
+        ChaCha8Rng::seed_from_u64(code.seed)
+    }
+
+    fn deal_key(first_turn: Turn, rng: &mut impl Rng) -> Key {
         let mut teams = {
             let (blue_cards, red_cards) = match first_turn {
                 Turn::Blue => (9, 8),
@@ -67,55 +697,265 @@ impl GameState {
                 .chain(vec![Team::Assassin; 1])
                 .chain(vec![Team::Neutral; 25 - red_cards - blue_cards - 1])
                 .collect();
-            teams.shuffle(&mut rng);
+            teams.shuffle(rng);
             teams.into_iter()
         };
 
-        let mut words = get_words().into_iter();
-        std::array::from_fn(|_| {
-            std::array::from_fn(|_| Card {
-                word: words.next().unwrap().to_string(),
-                team: teams.next().unwrap(),
-                revealed: false,
-            })
-        })
+        std::array::from_fn(|_| std::array::from_fn(|_| teams.next().unwrap()))
     }
 
-    pub fn new_game(&self) {
-        let new_first = match *self.first_turn.lock().unwrap() {
-            Turn::Blue => Turn::Red,
-            Turn::Red => Turn::Blue,
-        };
-        *self.first_turn.lock().unwrap() = new_first;
+    fn deal(code: GameCode, words: &WordSource) -> GameEvent {
+        GameEvent::BoardCreated {
+            board: Box::new(Self::new_board(code, words)),
+            first_turn: code.first_turn(),
+            code,
+        }
+    }
 
-        *self.board.lock().unwrap() = Self::new_board(*self.first_turn.lock().unwrap());
+    /// Starts a new game with the board `code` determines, or else a random board that the team
+    /// that went second last time starts, dealt from `words`; returns the new board's code
+    pub fn new_game(
+        &self,
+        code: Option<GameCode>,
+        words: &WordSource,
+    ) -> Result<GameCode, GameError> {
+        if let Some(code) = code
+            && code.words_version != words.version()
+        {
+            return Err(GameError::WordListMismatch {
+                version: code.words_version,
+                current: words.version(),
+            });
+        }
+
+        let mut engine = self.engine.lock().unwrap();
+        let code = code
+            .unwrap_or_else(|| GameCode::random(engine.game.first_turn.other(), words.version()));
+        engine.record(Self::deal(code, words));
+        Ok(code)
     }
 
     pub fn new(first_turn: Turn) -> Self {
+        Self::with_config(first_turn, GameConfig::default(), &WordSource::classic())
+    }
+
+    pub fn with_config(first_turn: Turn, config: GameConfig, words: &WordSource) -> Self {
+        let code = GameCode::random(first_turn, words.version());
         GameState {
-            board: Arc::new(Mutex::new(Self::new_board(first_turn))),
-            first_turn: Arc::new(Mutex::new(first_turn)),
+            config,
+            engine: Arc::new(Mutex::new(Engine::new(code, words))),
         }
     }
 
+    /// Restores a game from its event log, or returns `None` if the log has no board
+    pub fn from_events(events: Vec<RecordedEvent>, config: GameConfig) -> Option<Self> {
+        let game = Game::replay(&events)?;
+        Some(GameState {
+            config,
+            engine: Arc::new(Mutex::new(Engine {
+                events,
+                game,
+                undo_votes: BTreeSet::new(),
+            })),
+        })
+    }
+
+    /// Changes whenever the game does: every change appends to the log, which never shrinks
+    pub fn revision(&self) -> u64 {
+        self.engine.lock().unwrap().events.len() as u64
+    }
+
+    /// Every event recorded since the room was created, across all of its games
+    pub fn events(&self) -> Vec<RecordedEvent> {
+        self.engine.lock().unwrap().events.clone()
+    }
+
+    /// The full board, including the team of every card
+    pub fn key_json(&self) -> serde_json::Value {
+        self.engine.lock().unwrap().game.key_json()
+    }
+
     pub fn public_json(&self) -> serde_json::Value {
-        serde_json::json!(
-            self.board
-                .lock()
-                .unwrap()
-                .iter()
-                .map(|row| row.iter().map(Card::public_json).collect::<Vec<_>>())
-                .collect::<Vec<_>>()
-        )
+        self.engine.lock().unwrap().game.public_json()
     }
 
     pub fn spymaster_json(&self) -> serde_json::Value {
-        serde_json::json!(*self.board.lock().unwrap())
+        self.engine.lock().unwrap().game.spymaster_json()
+    }
+
+    /// Records the spymaster's clue for the current turn
+    pub fn give_clue(&self, team: Turn, word: &str, number: u8) -> Result<Clue, GameError> {
+        let mut engine = self.engine.lock().unwrap();
+        let game = &engine.game;
+
+        if game.game_over.is_some() {
+            return Err(GameError::GameOver);
+        }
+        if team != game.turn {
+            return Err(GameError::NotYourTurn { current: game.turn });
+        }
+        if game.current_record().is_some() {
+            return Err(GameError::ClueAlreadyGiven);
+        }
+
+        validate_clue(word, &game.board, self.config.lenient_clues)
+            .map_err(GameError::InvalidClue)?;
+        if number > MAX_CLUE_NUMBER {
+            return Err(GameError::InvalidClueNumber);
+        }
+
+        let clue = Clue {
+            team,
+            word: word.trim().to_string(),
+            number,
+        };
+        engine.record(GameEvent::ClueGiven { clue: clue.clone() });
+        Ok(clue)
+    }
+
+    /// Ends `team`'s turn early, returning the team whose turn it now is
+    pub fn end_turn(&self, team: Turn) -> Result<Turn, GameError> {
+        let mut engine = self.engine.lock().unwrap();
+        let game = &engine.game;
+
+        if game.game_over.is_some() {
+            return Err(GameError::GameOver);
+        }
+        if team != game.turn {
+            return Err(GameError::NotYourTurn { current: game.turn });
+        }
+        if game.current_record().is_none() {
+            return Err(GameError::NoClue);
+        }
+
+        engine.record(GameEvent::TurnEnded { team, passed: true });
+        Ok(engine.game.turn)
+    }
+
+    /// Reveals the card at the given position on behalf of `team`.
+    ///
+    /// Revealing a card that doesn't belong to the current team, or using up the clue's guesses,
+    /// passes the turn to the other team.
+    /// Revealing the assassin, or the last card of either team, ends the game.
+    pub fn reveal_card(
+        &self,
+        row: usize,
+        col: usize,
+        team: Turn,
+        player: Option<PlayerId>,
+    ) -> Result<Reveal, GameError> {
+        if row >= 5 || col >= 5 {
+            return Err(GameError::InvalidCoordinates);
+        }
+
+        let mut engine = self.engine.lock().unwrap();
+        let game = &engine.game;
+
+        if game.game_over.is_some() {
+            return Err(GameError::GameOver);
+        }
+        if team != game.turn {
+            return Err(GameError::NotYourTurn { current: game.turn });
+        }
+        if game.current_record().is_none() {
+            return Err(GameError::NoClue);
+        }
+        if game.board[row][col].revealed {
+            return Err(GameError::AlreadyRevealed);
+        }
+
+        let card_team = game.board[row][col].team;
+        engine.record(GameEvent::CardRevealed {
+            row,
+            col,
+            team: card_team,
+            player,
+        });
+
+        let game = &engine.game;
+        let game_over = match card_team {
+            Team::Assassin => Some(GameOver {
+                winner: team.other(),
+                reason: GameOverReason::Assassin,
+            }),
+            Team::Blue | Team::Red if game.all_found(card_team) => Some(GameOver {
+                winner: if card_team == Team::Blue {
+                    Turn::Blue
+                } else {
+                    Turn::Red
+                },
+                reason: GameOverReason::AllAgentsFound,
+            }),
+            _ => None,
+        };
+        // Checked above, so the current turn has a record
+        let out_of_guesses = game.guesses_remaining() == Some(0);
+
+        let turn_changed = if let Some(game_over) = game_over {
+            engine.record(GameEvent::GameOver(game_over));
+            None
+        } else if card_team != Team::from(team) || out_of_guesses {
+            engine.record(GameEvent::TurnEnded {
+                team,
+                passed: false,
+            });
+            Some(engine.game.turn)
+        } else {
+            None
+        };
+
+        Ok(Reveal {
+            card: engine.game.board[row][col].clone(),
+            turn_changed,
+            game_over,
+        })
     }
 
-    pub fn reveal_card(&self, row: usize, col: usize) -> Card {
-        self.board.lock().unwrap()[row][col].revealed = true;
-        self.board.lock().unwrap()[row][col].clone()
+    /// Hides the most recently revealed card again, restoring the turn and clearing any game over
+    /// it caused. Only allowed while the reveal is the latest action and within the undo window.
+    pub fn undo_reveal(&self) -> Result<Undo, GameError> {
+        let (_, undo) = self.undo(None)?;
+        // Without a vote to hold it back, the undo always goes through
+        Ok(undo.unwrap())
+    }
+
+    /// Records `player`'s vote to undo the last reveal; a majority of the `electorate` players
+    /// undoes it
+    pub fn vote_undo(
+        &self,
+        player: PlayerId,
+        electorate: usize,
+    ) -> Result<(UndoVotes, Option<Undo>), GameError> {
+        let (votes, undo) = self.undo(Some((player, electorate)))?;
+        // A vote always produces a tally
+        Ok((votes.unwrap(), undo))
+    }
+
+    // Undoes the last reveal, unless a vote is given and the reveal hasn't yet got a majority
+    fn undo(
+        &self,
+        vote: Option<(PlayerId, usize)>,
+    ) -> Result<(Option<UndoVotes>, Option<Undo>), GameError> {
+        let mut engine = self.engine.lock().unwrap();
+
+        let (row, col, at) = engine.last_reveal().ok_or(GameError::NothingToUndo)?;
+        if now_millis().saturating_sub(at) > self.config.undo_window.as_millis() as u64 {
+            return Err(GameError::UndoWindowClosed);
+        }
+
+        let votes = vote.map(|(player, electorate)| {
+            engine.undo_votes.insert(player);
+            UndoVotes {
+                votes: engine.undo_votes.len(),
+                needed: electorate / 2 + 1,
+            }
+        });
+        if votes.is_some_and(|votes| votes.votes < votes.needed) {
+            return Ok((votes, None));
+        }
+
+        engine.record(GameEvent::RevealUndone { row, col });
+        Ok((votes, Some(Undo { row, col })))
     }
 }
 
@@ -124,3 +964,188 @@ impl Default for GameState {
         Self::new(Turn::Blue)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A game whose blue spymaster has given a clue for two cards
+    fn game_with_clue() -> GameState {
+        let state = GameState::new(Turn::Blue);
+        state.give_clue(Turn::Blue, "qzx", 2).unwrap();
+        state
+    }
+
+    // The position of an unrevealed card of `team`
+    fn find(board: &Board, team: Team) -> (usize, usize) {
+        (0..25)
+            .map(|index| (index / 5, index % 5))
+            .find(|&(row, col)| board[row][col].team == team && !board[row][col].revealed)
+            .unwrap()
+    }
+
+    fn board(state: &GameState) -> Board {
+        state.engine.lock().unwrap().game.board.clone()
+    }
+
+    fn recorded(event: GameEvent) -> RecordedEvent {
+        RecordedEvent { at: 0, event }
+    }
+
+    #[test]
+    fn game_codes_round_trip() {
+        for seed in [0, 1, 42, u64::MAX] {
+            let code = GameCode {
+                words_version: 3,
+                seed,
+            };
+            assert_eq!(code.to_string().parse(), Ok(code));
+        }
+        let code = GameCode::random(Turn::Red, 1);
+        assert_eq!(code.to_string().len(), "1-".len() + SEED_LENGTH);
+        assert_eq!(code.to_string().parse(), Ok(code));
+        assert_eq!(code.first_turn(), Turn::Red);
+    }
+
+    #[test]
+    fn parses_game_codes_case_insensitively() {
+        assert_eq!(
+            "1-9k3m9qa0d2xht".parse::<GameCode>(),
+            "1-9K3M9QA0D2XHT".parse::<GameCode>()
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_game_codes() {
+        for code in [
+            "",
+            "1",
+            "1-",
+            "x-0000000",
+            // One digit too few, or too many
+            "1-0000000",
+            "1-000000000000",
+            "1-00000000000000",
+            // Thirteen digits, but more than 64 bits
+            "1-G000000000000",
+            "1-ZZZZZZZZZZZZZ",
+            // Letters left out of the alphabet as confusable with digits
+            "1-000000000000I",
+            "1-000000000000L",
+            "1-000000000000O",
+            "1-000000000000U",
+            "1-000000000000-",
+        ] {
+            assert_eq!(
+                code.parse::<GameCode>(),
+                Err(GameError::InvalidGameCode),
+                "{code:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn undoing_a_turn_ending_reveal_restores_the_turn() {
+        let state = game_with_clue();
+        let (row, col) = find(&board(&state), Team::Red);
+        let reveal = state.reveal_card(row, col, Turn::Blue, None).unwrap();
+        assert_eq!(reveal.turn_changed, Some(Turn::Red));
+
+        state.undo_reveal().unwrap();
+
+        // Both the live state and a fresh replay of the log drop the reveal and the turn change
+        let replayed = Game::replay(&state.events()).unwrap();
+        for game in [&state.engine.lock().unwrap().game, &replayed] {
+            assert!(!game.board[row][col].revealed);
+            assert_eq!(game.turn, Turn::Blue);
+            assert_eq!(game.guesses_remaining(), Some(3));
+        }
+    }
+
+    #[test]
+    fn replay_takes_back_an_undone_game_over() {
+        let code = GameCode {
+            words_version: WordSource::classic().version(),
+            seed: 42,
+        };
+        let board = GameState::new_board(code, &WordSource::classic());
+        let (row, col) = find(&board, Team::Assassin);
+        let events = [
+            GameEvent::BoardCreated {
+                board: Box::new(board),
+                first_turn: code.first_turn(),
+                code,
+            },
+            GameEvent::ClueGiven {
+                clue: Clue {
+                    team: code.first_turn(),
+                    word: "qzx".to_string(),
+                    number: 1,
+                },
+            },
+            GameEvent::CardRevealed {
+                row,
+                col,
+                team: Team::Assassin,
+                player: None,
+            },
+            GameEvent::GameOver(GameOver {
+                winner: code.first_turn().other(),
+                reason: GameOverReason::Assassin,
+            }),
+            GameEvent::RevealUndone { row, col },
+        ]
+        .map(recorded);
+
+        let game = Game::replay(&events).unwrap();
+        assert_eq!(game.game_over, None);
+        assert!(!game.board[row][col].revealed);
+        assert_eq!(game.turn, code.first_turn());
+        assert_eq!(game.guesses_remaining(), Some(2));
+    }
+
+    #[test]
+    fn undoing_a_game_ending_reveal_resumes_the_game() {
+        let state = game_with_clue();
+        let (row, col) = find(&board(&state), Team::Assassin);
+        let reveal = state.reveal_card(row, col, Turn::Blue, None).unwrap();
+        assert!(reveal.game_over.is_some());
+
+        state.undo_reveal().unwrap();
+
+        let game = &state.engine.lock().unwrap().game;
+        assert_eq!(game.game_over, None);
+        assert!(!game.board[row][col].revealed);
+        assert_eq!(game.turn, Turn::Blue);
+    }
+
+    #[test]
+    fn refuses_to_undo_a_reveal_from_the_last_board() {
+        let state = game_with_clue();
+        let (row, col) = find(&board(&state), Team::Blue);
+        state.reveal_card(row, col, Turn::Blue, None).unwrap();
+        state.new_game(None, &WordSource::classic()).unwrap();
+
+        assert_eq!(state.undo_reveal().unwrap_err(), GameError::NothingToUndo);
+    }
+
+    #[test]
+    fn restores_a_game_from_its_serialized_events() {
+        let state = game_with_clue();
+        let (row, col) = find(&board(&state), Team::Blue);
+        state.reveal_card(row, col, Turn::Blue, None).unwrap();
+        let (row, col) = find(&board(&state), Team::Neutral);
+        state.reveal_card(row, col, Turn::Blue, None).unwrap();
+        state.undo_reveal().unwrap();
+        state.end_turn(Turn::Blue).unwrap();
+
+        let json = serde_json::to_string(&state.events()).unwrap();
+        let restored =
+            GameState::from_events(serde_json::from_str(&json).unwrap(), GameConfig::default())
+                .unwrap();
+
+        assert_eq!(restored.spymaster_json(), state.spymaster_json());
+        assert_eq!(restored.public_json(), state.public_json());
+        assert_eq!(restored.revision(), state.revision());
+    }
+}
@@ -10,10 +10,24 @@ use shuttle_actix_web::ShuttleActixWeb;
 mod api;
 mod frontend;
 mod game;
+mod player;
+mod protocol;
 mod public;
+mod room;
+mod shutdown;
+mod sse;
+mod storage;
+mod validation;
 mod websocket;
 mod words;
 
+#[cfg(feature = "shuttle")]
+const DATA_FILE_VAR: &str = "CODENAMES_DATA_FILE";
+#[cfg(feature = "shuttle")]
+const WORDS_VAR: &str = "CODENAMES_WORDS";
+#[cfg(feature = "shuttle")]
+const ADMIN_TOKEN_VAR: &str = "CODENAMES_ADMIN_TOKEN";
+
 #[derive(Parser, Debug)]
 /// A web application implementation of Codenames
 #[command(author, version, about, long_about = None)]
@@ -25,12 +39,38 @@ pub struct Args {
     /// Port to listen on
     #[arg(short, long, default_value_t = 8080)]
     pub port: u16,
+
+    /// Only refuse clues that exactly match a word on the board (house rules)
+    #[arg(long)]
+    pub lenient_clues: bool,
+
+    /// Minutes a room may sit idle with no connections before it is removed
+    #[arg(long, default_value_t = room::DEFAULT_ROOM_TTL.as_secs() / 60)]
+    pub room_ttl: u64,
+
+    /// Seconds after a reveal during which it may be undone
+    #[arg(long, default_value_t = game::DEFAULT_UNDO_WINDOW.as_secs())]
+    pub undo_window: u64,
+
+    /// JSON file to save rooms and uploaded word packs to, so games in progress survive a restart
+    #[arg(long)]
+    pub data_file: Option<std::path::PathBuf>,
+
+    /// Word pack files, each a JSON array of strings or one word per line and named after the
+    /// file. Games are dealt from these instead of the built-in words unless they choose packs.
+    #[arg(long, value_name = "PATH", num_args = 1..)]
+    pub words: Vec<std::path::PathBuf>,
+
+    /// Secret for uploading, changing and deleting word packs; without one, packs can only be
+    /// loaded with `--words`
+    #[arg(long)]
+    pub admin_token: Option<String>,
 }
 
 fn config(
     cfg: &mut web::ServiceConfig,
-    game_state: web::Data<game::GameState>,
-    ws_state: web::Data<websocket::WsState>,
+    rooms: web::Data<room::RoomRegistry>,
+    word_packs: web::Data<words::WordPacks>,
 ) {
     let cors = || {
         // TODO: only run permissively if this is a debug build
@@ -39,23 +79,115 @@ fn config(
 
     let api = web::scope("/api")
         .wrap(cors())
-        .route("/board/public", web::get().to(api::get_board_public))
-        .route("/board/spymaster", web::get().to(api::get_board_spymaster))
-        .route("/reveal", web::post().to(api::post_reveal))
-        .route("/new_game", web::post().to(api::post_new_game));
+        .route("/rooms", web::post().to(api::post_room))
+        .route("/keycards", web::post().to(api::post_key_card))
+        .route("/keycards/{code}", web::get().to(api::get_key_card))
+        .route("/wordpacks", web::get().to(api::get_word_packs))
+        .route("/wordpacks", web::post().to(api::post_word_pack))
+        .route("/wordpacks/{name}", web::get().to(api::get_word_pack))
+        .route("/wordpacks/{name}", web::put().to(api::put_word_pack))
+        .route("/wordpacks/{name}", web::delete().to(api::delete_word_pack))
+        .service(
+            web::scope("/{room}")
+                .route("/board/public", web::get().to(api::get_board_public))
+                .route("/board/spymaster", web::get().to(api::get_board_spymaster))
+                .route("/events", web::get().to(api::get_events))
+                .route("/seats", web::get().to(api::get_seats))
+                .route("/seats/{team}/claim", web::post().to(api::post_claim_seat))
+                .route(
+                    "/seats/{team}/release",
+                    web::post().to(api::post_release_seat),
+                )
+                .route("/players", web::get().to(api::get_players))
+                .route("/players", web::post().to(api::post_join))
+                .route("/players/me", web::get().to(api::get_session))
+                .route("/players/me/team", web::post().to(api::post_change_team))
+                .route("/players/me/leave", web::post().to(api::post_leave))
+                .route("/reveal", web::post().to(api::post_reveal))
+                .route("/undo", web::post().to(api::post_undo))
+                .route("/undo/vote", web::post().to(api::post_vote_undo))
+                .route("/clue", web::post().to(api::post_clue))
+                .route("/end_turn", web::post().to(api::post_end_turn))
+                .route("/chat", web::post().to(api::post_chat))
+                .route("/new_game", web::post().to(api::post_new_game)),
+        );
 
     let ws = web::scope("/ws")
         .wrap(cors())
-        .route("/public", web::get().to(websocket::get_public))
-        .route("/spymaster", web::get().to(websocket::get_spymaster));
+        .route("/{room}/public", web::get().to(websocket::get_public))
+        .route("/{room}/spymaster", web::get().to(websocket::get_spymaster));
 
-    cfg.app_data(game_state)
-        .app_data(ws_state)
+    let sse = web::scope("/sse")
+        .wrap(cors())
+        .route("/{room}/public", web::get().to(sse::get_public))
+        .route("/{room}/spymaster", web::get().to(sse::get_spymaster));
+
+    cfg.app_data(rooms)
+        .app_data(word_packs)
         .service(api)
         .service(ws)
+        .service(sse)
         .route("/{path:.*}", web::get().to(frontend::get_frontend));
 }
 
+/// The built-in word pack along with one loaded from each of `paths`, managed with `admin_token`
+fn load_word_packs(
+    paths: &[std::path::PathBuf],
+    admin_token: Option<String>,
+) -> std::io::Result<words::WordPacks> {
+    let invalid = |err: &dyn std::error::Error| {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, err.to_string())
+    };
+
+    let packs = paths
+        .iter()
+        .map(|path| words::WordPack::load(path))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| invalid(&err))?;
+    for pack in &packs {
+        log::info!(
+            "Loaded word pack \"{}\" with {} words (word list version {})",
+            pack.name,
+            pack.words.len(),
+            pack.version
+        );
+    }
+
+    if admin_token.is_none() {
+        log::info!("No admin token was given, so word packs can't be uploaded");
+    }
+    words::WordPacks::new(packs, admin_token).map_err(|err| invalid(&err))
+}
+
+/// Restores the rooms and word packs saved in `path`, then keeps it up to date as they change
+fn restore_and_save_rooms(
+    rooms: &web::Data<room::RoomRegistry>,
+    path: &std::path::Path,
+) -> std::io::Result<storage::Storage> {
+    let storage = storage::Storage::new(path);
+    let saved = storage.load().map_err(|err| {
+        std::io::Error::new(
+            err.kind(),
+            format!("couldn't read saved rooms from {}: {err}", path.display()),
+        )
+    })?;
+    let restored = rooms.word_packs().restore(saved.word_packs);
+    if restored > 0 {
+        log::info!("Restored {restored} word packs from {}", path.display());
+    }
+    let restored = rooms.restore(saved.rooms);
+    if restored > 0 {
+        log::info!("Restored {restored} rooms from {}", path.display());
+    }
+
+    let storage_rooms = rooms.clone();
+    let task_storage = storage.clone();
+    tokio::spawn(async move {
+        storage::storage_task(storage_rooms, task_storage).await;
+    });
+    Ok(storage)
+}
+
 #[cfg(not(feature = "shuttle"))]
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
@@ -63,39 +195,95 @@ async fn main() -> std::io::Result<()> {
 
     env_logger::init();
 
-    let game_state = web::Data::new(game::GameState::default());
-    let ws_state = web::Data::new(websocket::WsState::new());
+    let word_packs = load_word_packs(&args.words, args.admin_token.clone())?;
+
+    let game_config = game::GameConfig {
+        lenient_clues: args.lenient_clues,
+        undo_window: std::time::Duration::from_secs(args.undo_window),
+    };
+    let rooms = web::Data::new(room::RoomRegistry::new(game_config, word_packs.clone()));
+    let word_packs = web::Data::new(word_packs);
+
+    let storage = args
+        .data_file
+        .as_deref()
+        .map(|path| restore_and_save_rooms(&rooms, path))
+        .transpose()?;
 
-    let cleanup_ws_state = ws_state.clone();
+    let cleanup_rooms = rooms.clone();
     tokio::spawn(async move {
-        websocket::websocket_cleanup_task(cleanup_ws_state).await;
+        websocket::websocket_cleanup_task(cleanup_rooms).await;
     });
 
-    HttpServer::new(move || {
-        App::new().configure(|cfg| config(cfg, game_state.clone(), ws_state.clone()))
+    let reaper_rooms = rooms.clone();
+    let room_ttl = std::time::Duration::from_secs(args.room_ttl * 60);
+    tokio::spawn(async move {
+        room::room_cleanup_task(reaper_rooms, room_ttl).await;
+    });
+
+    let shutdown_rooms = rooms.clone();
+    let server = HttpServer::new(move || {
+        App::new().configure(|cfg| config(cfg, rooms.clone(), word_packs.clone()))
     })
+    // Signals are handled below, so clients can be told about the shutdown first
+    .disable_signals()
     .bind((args.host.clone(), args.port))
     .inspect(|_| {
         println!("Codenames running at http://{}:{}", args.host, args.port);
     })?
-    .run()
-    .await
+    .run();
+
+    let server_handle = server.handle();
+    tokio::spawn(async move {
+        shutdown::signal().await;
+        log::info!("Shutting down");
+        shutdown::shut_down(&shutdown_rooms, storage.as_ref());
+        server_handle.stop(true).await;
+    });
+
+    server.await
 }
 
 #[cfg(feature = "shuttle")]
 #[shuttle_runtime::main]
 async fn main() -> ShuttleActixWeb<impl FnOnce(&mut web::ServiceConfig) + Send + Clone + 'static> {
-    let game_state = web::Data::new(game::GameState::default());
-    let ws_state = web::Data::new(websocket::WsState::new());
+    let word_paths = std::env::var_os(WORDS_VAR)
+        .map(|paths| std::env::split_paths(&paths).collect::<Vec<_>>())
+        .unwrap_or_default();
+    let admin_token = std::env::var(ADMIN_TOKEN_VAR).ok();
+    let word_packs = load_word_packs(&word_paths, admin_token)?;
+
+    let rooms = web::Data::new(room::RoomRegistry::new(
+        game::GameConfig::default(),
+        word_packs.clone(),
+    ));
+    let word_packs = web::Data::new(word_packs);
+
+    // Shuttle doesn't pass command line arguments, so the data file comes from the environment
+    let storage = std::env::var_os(DATA_FILE_VAR)
+        .map(|path| restore_and_save_rooms(&rooms, path.as_ref()))
+        .transpose()?;
+
+    // Shuttle stops the server itself, but clients can still be warned and rooms saved first
+    let shutdown_rooms = rooms.clone();
+    tokio::spawn(async move {
+        shutdown::signal().await;
+        shutdown::shut_down(&shutdown_rooms, storage.as_ref());
+    });
+
+    let cleanup_rooms = rooms.clone();
+    tokio::spawn(async move {
+        websocket::websocket_cleanup_task(cleanup_rooms).await;
+    });
 
-    let cleanup_ws_state = ws_state.clone();
+    let reaper_rooms = rooms.clone();
     tokio::spawn(async move {
-        websocket::websocket_cleanup_task(cleanup_ws_state).await;
+        room::room_cleanup_task(reaper_rooms, room::DEFAULT_ROOM_TTL).await;
     });
 
     Ok(shuttle_actix_web::ActixWebService(
         move |cfg: &mut web::ServiceConfig| {
-            config(cfg, game_state, ws_state);
+            config(cfg, rooms, word_packs);
         },
     ))
 }
@@ -0,0 +1,376 @@
+use actix_web::dev::Payload;
+use actix_web::{FromRequest, HttpRequest, HttpResponse, ResponseError};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::future::{Ready, ready};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::api::ApiError;
+use crate::game::Turn;
+use crate::room::{Room, constant_time_eq, random_token, session_token};
+
+const MAX_NICKNAME_LENGTH: usize = 24;
+pub const MAX_CHAT_LENGTH: usize = 500;
+
+pub type PlayerId = u64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Operative,
+    Spymaster,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Player {
+    pub id: PlayerId,
+    pub nickname: String,
+    pub team: Turn,
+    pub role: Role,
+    /// Whether the player has a live connection to the game
+    pub connected: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PlayerError {
+    NotFound(PlayerId),
+    InvalidNickname,
+    NotOnTeam(Turn),
+    NotOperative,
+    NotSpymaster,
+    InvalidSession,
+    AlreadyJoined,
+    InvalidChatMessage,
+}
+
+impl ApiError for PlayerError {
+    fn kind(&self) -> &'static str {
+        match self {
+            PlayerError::NotFound(_) => "Player not found",
+            PlayerError::InvalidNickname => "Invalid nickname",
+            PlayerError::NotOnTeam(_) => "Not on team",
+            PlayerError::NotOperative => "Not an operative",
+            PlayerError::NotSpymaster => "Not a spymaster",
+            PlayerError::InvalidSession => "Invalid session",
+            PlayerError::AlreadyJoined => "Already joined",
+            PlayerError::InvalidChatMessage => "Invalid chat message",
+        }
+    }
+}
+
+impl std::fmt::Display for PlayerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PlayerError::NotFound(id) => write!(f, "There is no player {id} in this game."),
+            PlayerError::InvalidNickname => write!(
+                f,
+                "Nicknames must be between 1 and {MAX_NICKNAME_LENGTH} characters long."
+            ),
+            PlayerError::NotOnTeam(team) => write!(f, "That player isn't on the {team:?} team."),
+            PlayerError::NotOperative => {
+                write!(
+                    f,
+                    "Only operatives can reveal cards and end their team's turn."
+                )
+            }
+            PlayerError::NotSpymaster => {
+                write!(f, "Only spymasters can claim their team's spymaster seat.")
+            }
+            PlayerError::InvalidSession => {
+                write!(f, "A valid session token is required; join the game first.")
+            }
+            PlayerError::AlreadyJoined => write!(f, "This connection has already joined the game."),
+            PlayerError::InvalidChatMessage => write!(
+                f,
+                "Chat messages must be between 1 and {MAX_CHAT_LENGTH} characters long."
+            ),
+        }
+    }
+}
+
+impl ResponseError for PlayerError {
+    fn error_response(&self) -> HttpResponse {
+        let mut response = match self {
+            PlayerError::NotFound(_) => HttpResponse::NotFound(),
+            PlayerError::InvalidNickname => HttpResponse::BadRequest(),
+            PlayerError::NotOnTeam(_) | PlayerError::NotOperative | PlayerError::NotSpymaster => {
+                HttpResponse::Forbidden()
+            }
+            PlayerError::InvalidSession => HttpResponse::Unauthorized(),
+            PlayerError::AlreadyJoined => HttpResponse::Conflict(),
+            PlayerError::InvalidChatMessage => HttpResponse::BadRequest(),
+        };
+        response.json(self.body())
+    }
+}
+
+/// How long a player whose connections have all closed keeps their place, so a reload or a
+/// dropped connection doesn't cost them their team, role and seat
+pub const SESSION_GRACE: Duration = Duration::from_secs(2 * 60);
+
+/// A player along with the session that identifies them
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Member {
+    player: Player,
+    /// Secret handed out on joining, used to act as and resume this player
+    session_token: String,
+    /// Seat token of the spymaster seat claimed during this session
+    seat_token: Option<String>,
+    #[serde(skip)]
+    connections: usize,
+    #[serde(skip, default = "Instant::now")]
+    last_seen: Instant,
+}
+
+// The players who have joined a room
+#[derive(Debug, Clone)]
+pub struct Roster {
+    players: Arc<Mutex<BTreeMap<PlayerId, Member>>>,
+    next_id: Arc<AtomicU64>,
+    // Counts changes to what is saved of each player, which leaves out their connections
+    revision: Arc<AtomicU64>,
+}
+
+impl Roster {
+    pub fn new() -> Self {
+        Self {
+            players: Arc::new(Mutex::new(BTreeMap::new())),
+            next_id: Arc::new(AtomicU64::new(1)),
+            revision: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Rebuilds a saved roster. Nobody is connected yet, so players who don't come back are removed
+    /// once the session grace period has passed
+    pub fn restore(members: Vec<Member>) -> Self {
+        let next_id = members
+            .iter()
+            .map(|member| member.player.id)
+            .max()
+            .unwrap_or(0)
+            + 1;
+        let players = members
+            .into_iter()
+            .map(|mut member| {
+                member.player.connected = false;
+                (member.player.id, member)
+            })
+            .collect();
+        Self {
+            players: Arc::new(Mutex::new(players)),
+            next_id: Arc::new(AtomicU64::new(next_id)),
+            revision: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    pub fn revision(&self) -> u64 {
+        self.revision.load(Ordering::SeqCst)
+    }
+
+    fn changed(&self) {
+        self.revision.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Every player along with their tokens, for saving to disk
+    pub fn members(&self) -> Vec<Member> {
+        self.players.lock().unwrap().values().cloned().collect()
+    }
+
+    /// Adds a player, returning them along with their session token
+    pub fn join(
+        &self,
+        nickname: &str,
+        team: Turn,
+        role: Role,
+    ) -> Result<(Player, String), PlayerError> {
+        let nickname = nickname.trim();
+        if nickname.is_empty() || nickname.chars().count() > MAX_NICKNAME_LENGTH {
+            return Err(PlayerError::InvalidNickname);
+        }
+
+        let player = Player {
+            id: self.next_id.fetch_add(1, Ordering::SeqCst),
+            nickname: nickname.to_string(),
+            team,
+            role,
+            connected: false,
+        };
+        let session_token = random_token();
+        self.players.lock().unwrap().insert(
+            player.id,
+            Member {
+                player: player.clone(),
+                session_token: session_token.clone(),
+                seat_token: None,
+                connections: 0,
+                last_seen: Instant::now(),
+            },
+        );
+        self.changed();
+        Ok((player, session_token))
+    }
+
+    pub fn get(&self, id: PlayerId) -> Result<Player, PlayerError> {
+        self.players
+            .lock()
+            .unwrap()
+            .get(&id)
+            .map(|member| member.player.clone())
+            .ok_or(PlayerError::NotFound(id))
+    }
+
+    /// The player a session token belongs to. Using the token counts as being seen, so players who
+    /// only make HTTP requests keep their place too.
+    pub fn session(&self, token: &str) -> Result<PlayerId, PlayerError> {
+        let mut players = self.players.lock().unwrap();
+        let member = players
+            .values_mut()
+            .find(|member| constant_time_eq(token.as_bytes(), member.session_token.as_bytes()))
+            .ok_or(PlayerError::InvalidSession)?;
+        member.last_seen = Instant::now();
+        Ok(member.player.id)
+    }
+
+    pub fn seat_token(&self, id: PlayerId) -> Option<String> {
+        self.players
+            .lock()
+            .unwrap()
+            .get(&id)
+            .and_then(|member| member.seat_token.clone())
+    }
+
+    /// Remembers the seat a player claimed, so it can be handed back when they resume
+    pub fn set_seat_token(&self, id: PlayerId, seat_token: Option<String>) {
+        if let Some(member) = self.players.lock().unwrap().get_mut(&id) {
+            member.seat_token = seat_token;
+            self.changed();
+        }
+    }
+
+    pub fn change_team(&self, id: PlayerId, team: Turn, role: Role) -> Result<Player, PlayerError> {
+        let mut players = self.players.lock().unwrap();
+        let member = players.get_mut(&id).ok_or(PlayerError::NotFound(id))?;
+        member.player.team = team;
+        member.player.role = role;
+        self.changed();
+        Ok(member.player.clone())
+    }
+
+    /// Removes a player, returning them along with the seat token of any seat they claimed
+    pub fn leave(&self, id: PlayerId) -> Result<(Player, Option<String>), PlayerError> {
+        let member = self
+            .players
+            .lock()
+            .unwrap()
+            .remove(&id)
+            .ok_or(PlayerError::NotFound(id))?;
+        self.changed();
+        Ok((member.player, member.seat_token))
+    }
+
+    /// Records a new connection for the player, returning them if they were offline until now
+    pub fn connect(&self, id: PlayerId) -> Option<Player> {
+        let mut players = self.players.lock().unwrap();
+        let member = players.get_mut(&id)?;
+        member.connections += 1;
+        member.last_seen = Instant::now();
+        if member.player.connected {
+            return None;
+        }
+        member.player.connected = true;
+        Some(member.player.clone())
+    }
+
+    /// Records a closed connection, returning the player if it was their last one
+    pub fn disconnect(&self, id: PlayerId) -> Option<Player> {
+        let mut players = self.players.lock().unwrap();
+        let member = players.get_mut(&id)?;
+        member.connections = member.connections.saturating_sub(1);
+        member.last_seen = Instant::now();
+        if member.connections > 0 {
+            return None;
+        }
+        member.player.connected = false;
+        Some(member.player.clone())
+    }
+
+    /// Players who have had no connection, and made no request, for longer than `grace`
+    pub fn abandoned(&self, grace: Duration) -> Vec<PlayerId> {
+        self.players
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|member| member.connections == 0 && member.last_seen.elapsed() > grace)
+            .map(|member| member.player.id)
+            .collect()
+    }
+
+    /// Checks that the player exists and is one of `team`'s operatives, who are the ones that
+    /// guess; spymasters give clues instead. Anyone may guess in a room without a roster, which is
+    /// played from a shared screen, but once players have joined only they can.
+    pub fn check_operative(&self, id: Option<PlayerId>, team: Turn) -> Result<(), PlayerError> {
+        self.check_role(id, team, Role::Operative)
+    }
+
+    /// Checks that the player may sit in `team`'s spymaster seat, on the same terms as
+    /// `check_operative`
+    pub fn check_spymaster(&self, id: Option<PlayerId>, team: Turn) -> Result<(), PlayerError> {
+        self.check_role(id, team, Role::Spymaster)
+    }
+
+    fn check_role(&self, id: Option<PlayerId>, team: Turn, role: Role) -> Result<(), PlayerError> {
+        let Some(id) = id else {
+            return if self.players.lock().unwrap().is_empty() {
+                Ok(())
+            } else {
+                Err(PlayerError::InvalidSession)
+            };
+        };
+
+        let player = self.get(id)?;
+        if player.team != team {
+            return Err(PlayerError::NotOnTeam(team));
+        }
+        if player.role != role {
+            return Err(match role {
+                Role::Operative => PlayerError::NotOperative,
+                Role::Spymaster => PlayerError::NotSpymaster,
+            });
+        }
+        Ok(())
+    }
+
+    pub fn players(&self) -> Vec<Player> {
+        self.players
+            .lock()
+            .unwrap()
+            .values()
+            .map(|member| member.player.clone())
+            .collect()
+    }
+}
+
+/// A room and the player whose session token came with the request
+pub struct PlayerSession {
+    pub room: Room,
+    pub player: PlayerId,
+}
+
+impl FromRequest for PlayerSession {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let session = Room::from_request(req, payload)
+            .into_inner()
+            .map_err(actix_web::Error::from)
+            .and_then(|room| {
+                let token = session_token(req).ok_or(PlayerError::InvalidSession)?;
+                let player = room.players.session(&token)?;
+                Ok(PlayerSession { room, player })
+            });
+        ready(session)
+    }
+}
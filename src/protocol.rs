@@ -0,0 +1,54 @@
+use serde::Serialize;
+
+/// Versions of the WebSocket message schema
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProtocolVersion {
+    /// Spoken to clients that don't ask for a version: `new_game` carries just the bare 5x5
+    /// board, as it always has. Messages that v1 clients never had, like `snapshot`, are sent
+    /// to them as they are.
+    V1,
+    /// `new_game` carries the client's whole view of the new game, `{board, turn, ...}`, so that
+    /// the turn and clue log are reset along with the board
+    V2,
+}
+
+impl ProtocolVersion {
+    pub const SUPPORTED: [ProtocolVersion; 2] = [ProtocolVersion::V1, ProtocolVersion::V2];
+
+    /// The version to speak with a client asking for `requested`: that version if supported,
+    /// otherwise the latest one older than it, falling back to the oldest
+    pub fn negotiate(requested: u32) -> Self {
+        Self::SUPPORTED
+            .into_iter()
+            .rev()
+            .find(|version| version.number() <= requested)
+            .unwrap_or(ProtocolVersion::V1)
+    }
+
+    pub fn number(self) -> u32 {
+        match self {
+            ProtocolVersion::V1 => 1,
+            ProtocolVersion::V2 => 2,
+        }
+    }
+
+    /// Serializes a message in this version's schema; messages are built in the latest schema
+    /// and adapted for older clients
+    pub fn encode(self, message: &impl Serialize) -> serde_json::Result<String> {
+        match self {
+            ProtocolVersion::V2 => serde_json::to_string(message),
+            ProtocolVersion::V1 => {
+                let mut value = serde_json::to_value(message)?;
+                downgrade_to_v1(&mut value);
+                serde_json::to_string(&value)
+            }
+        }
+    }
+}
+
+fn downgrade_to_v1(message: &mut serde_json::Value) {
+    if message["type"] == "new_game" {
+        let board = message["data"]["board"].take();
+        message["data"] = board;
+    }
+}
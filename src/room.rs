@@ -0,0 +1,500 @@
+use actix_web::dev::Payload;
+use actix_web::http::header;
+use actix_web::{FromRequest, HttpRequest, HttpResponse, ResponseError, web};
+use rand::Rng;
+use rand::distr::Alphanumeric;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::future::{Ready, ready};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::api::{self, ApiError};
+use crate::game::{GameConfig, GameState, Turn};
+use crate::player::{PlayerId, Roster, SESSION_GRACE};
+use crate::storage::SavedRoom;
+use crate::websocket::WsState;
+use crate::words::WordPacks;
+
+/// Letters used in room codes; I and O are left out so they can't be confused with 1 and 0
+const CODE_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ";
+const CODE_LENGTH: usize = 4;
+/// Most rooms open at once; well below the 331,776 possible codes, so a free one is quick to find
+const MAX_ROOMS: usize = 10_000;
+/// Random codes tried before giving up, in case expired codes fill the rest of the space
+const CODE_ATTEMPTS: usize = 100;
+const TOKEN_LENGTH: usize = 32;
+const SEAT_TOKEN_HEADER: &str = "X-Seat-Token";
+const SESSION_TOKEN_HEADER: &str = "X-Session-Token";
+
+/// How often idle rooms are looked for
+const REAP_INTERVAL: Duration = Duration::from_secs(60);
+/// How long the codes of expired rooms are remembered, so late clients can be told what happened
+const EXPIRED_MEMORY: Duration = Duration::from_secs(24 * 60 * 60);
+pub const DEFAULT_ROOM_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// A single game and the clients connected to it
+#[derive(Debug, Clone)]
+pub struct Room {
+    pub code: String,
+    pub game: GameState,
+    pub ws: WsState,
+    pub players: Roster,
+    /// The word packs new games in the room can be dealt from
+    pub word_packs: WordPacks,
+    /// Secret that grants access to the spymaster view; handed out only when the room is created
+    pub spymaster_token: String,
+    /// Seat tokens of the spymasters currently holding each team's seat
+    seats: Arc<Mutex<HashMap<Turn, String>>>,
+    // Counts changes to the seats, so saving can tell when they need writing again
+    seats_revision: Arc<AtomicU64>,
+    last_activity: Arc<Mutex<Instant>>,
+}
+
+impl Room {
+    /// Marks the room as in use, postponing its expiry
+    pub fn touch(&self) {
+        *self.last_activity.lock().unwrap() = Instant::now();
+    }
+
+    pub fn idle_time(&self) -> Duration {
+        self.last_activity.lock().unwrap().elapsed()
+    }
+
+    /// Checks the spymaster token sent as a bearer token, or as the `token` query parameter for
+    /// WebSocket upgrades, where browsers can't set headers
+    pub fn authorize_spymaster(&self, req: &HttpRequest) -> Result<(), RoomError> {
+        let token = bearer_token(req).or_else(|| TokenParams::from_request(req).token);
+
+        match token {
+            Some(token) if constant_time_eq(token.as_bytes(), self.spymaster_token.as_bytes()) => {
+                Ok(())
+            }
+            _ => Err(RoomError::Unauthorized),
+        }
+    }
+
+    /// Gives `team`'s spymaster seat to the caller, returning the seat token that identifies them.
+    /// `held` is the caller's seat token, if they have one: nobody may hold both seats.
+    pub fn claim_seat(&self, team: Turn, held: Option<&str>) -> Result<String, RoomError> {
+        let mut seats = self.seats.lock().unwrap();
+        if let Some((&held_team, _)) = held.and_then(|held| {
+            seats
+                .iter()
+                .find(|(_, token)| constant_time_eq(held.as_bytes(), token.as_bytes()))
+        }) {
+            return Err(RoomError::AlreadySeated(held_team));
+        }
+        if seats.contains_key(&team) {
+            return Err(RoomError::SeatTaken(team));
+        }
+
+        let token = random_token();
+        seats.insert(team, token.clone());
+        self.seats_revision.fetch_add(1, Ordering::SeqCst);
+        Ok(token)
+    }
+
+    /// Frees `team`'s seat; allowed for the seat's holder, or anyone with the spymaster token
+    pub fn release_seat(&self, team: Turn, req: &HttpRequest) -> Result<(), RoomError> {
+        let holds_seat = seat_token(req).is_some_and(|token| self.seat_team(&token) == Some(team));
+        if !holds_seat {
+            self.authorize_spymaster(req)?;
+        }
+
+        self.seats.lock().unwrap().remove(&team);
+        self.seats_revision.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Frees whichever seat is held with the given seat token, returning whether there was one
+    pub fn vacate_seat(&self, token: &str) -> bool {
+        let mut seats = self.seats.lock().unwrap();
+        let len = seats.len();
+        seats.retain(|_, held| !constant_time_eq(token.as_bytes(), held.as_bytes()));
+        let vacated = seats.len() != len;
+        if vacated {
+            self.seats_revision.fetch_add(1, Ordering::SeqCst);
+        }
+        vacated
+    }
+
+    /// The seat token a request acts with: the one it sent, or else the one its player claimed
+    pub fn request_seat_token(
+        &self,
+        req: &HttpRequest,
+        player: Option<PlayerId>,
+    ) -> Option<String> {
+        seat_token(req).or_else(|| player.and_then(|id| self.players.seat_token(id)))
+    }
+
+    /// The team whose seat is held with the given seat token
+    pub fn seat_team(&self, token: &str) -> Option<Turn> {
+        self.seats
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|(_, held)| constant_time_eq(token.as_bytes(), held.as_bytes()))
+            .map(|(team, _)| *team)
+    }
+
+    /// Changes whenever anything saved with the room does. Each part only ever counts up, so their
+    /// sum does too.
+    pub fn revision(&self) -> u64 {
+        self.game.revision()
+            + self.players.revision()
+            + self.seats_revision.load(Ordering::SeqCst)
+            + self.ws.last_seq()
+    }
+
+    /// Everything needed to restore the room after a restart
+    pub fn save(&self) -> SavedRoom {
+        SavedRoom {
+            code: self.code.clone(),
+            spymaster_token: self.spymaster_token.clone(),
+            seats: self.seats.lock().unwrap().clone(),
+            players: self.players.members(),
+            events: self.game.events(),
+            seq: self.ws.last_seq(),
+        }
+    }
+
+    /// Which seats are taken, without revealing their tokens
+    pub fn seats_json(&self) -> serde_json::Value {
+        let seats = self.seats.lock().unwrap();
+        serde_json::json!({
+            "blue": seats.contains_key(&Turn::Blue),
+            "red": seats.contains_key(&Turn::Red),
+        })
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct TokenParams {
+    token: Option<String>,
+    seat: Option<String>,
+    session: Option<String>,
+}
+
+impl TokenParams {
+    fn from_request(req: &HttpRequest) -> Self {
+        web::Query::<Self>::from_query(req.query_string())
+            .map(web::Query::into_inner)
+            .unwrap_or_default()
+    }
+}
+
+/// The seat token sent in the `X-Seat-Token` header, or as the `seat` query parameter
+pub fn seat_token(req: &HttpRequest) -> Option<String> {
+    req.headers()
+        .get(SEAT_TOKEN_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string)
+        .or_else(|| TokenParams::from_request(req).seat)
+}
+
+/// The token sent in an `Authorization: Bearer` header
+pub fn bearer_token(req: &HttpRequest) -> Option<String> {
+    req.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::to_string)
+}
+
+/// The player session token sent in the `X-Session-Token` header, or as the `session` query
+/// parameter
+pub fn session_token(req: &HttpRequest) -> Option<String> {
+    req.headers()
+        .get(SESSION_TOKEN_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string)
+        .or_else(|| TokenParams::from_request(req).session)
+}
+
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+pub fn random_token() -> String {
+    rand::rng()
+        .sample_iter(Alphanumeric)
+        .take(TOKEN_LENGTH)
+        .map(char::from)
+        .collect()
+}
+
+/// A room whose spymaster token has been checked
+pub struct SpymasterRoom(pub Room);
+
+/// A room and the team whose spymaster seat the caller holds
+pub struct SeatedSpymaster {
+    pub room: Room,
+    pub team: Turn,
+}
+
+#[derive(Debug)]
+pub enum RoomError {
+    NotFound(String),
+    Expired(String),
+    Unauthorized,
+    SeatTaken(Turn),
+    AlreadySeated(Turn),
+    NotSeated,
+    Full,
+}
+
+impl std::fmt::Display for RoomError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RoomError::NotFound(code) => write!(f, "There is no game with the code {code}."),
+            RoomError::Expired(code) => {
+                write!(f, "The game {code} expired after being left idle.")
+            }
+            RoomError::Unauthorized => write!(f, "A valid spymaster token is required."),
+            RoomError::SeatTaken(team) => {
+                write!(f, "The {team:?} spymaster seat is already taken.")
+            }
+            RoomError::AlreadySeated(team) => {
+                write!(f, "You already hold the {team:?} spymaster seat.")
+            }
+            RoomError::NotSeated => write!(f, "You don't hold a spymaster seat in this game."),
+            RoomError::Full => write!(f, "Too many games are in progress; try again later."),
+        }
+    }
+}
+
+impl ApiError for RoomError {
+    fn kind(&self) -> &'static str {
+        match self {
+            RoomError::NotFound(_) => "Room not found",
+            RoomError::Expired(_) => "Room expired",
+            RoomError::Unauthorized => "Unauthorized",
+            RoomError::SeatTaken(_) => "Seat taken",
+            RoomError::AlreadySeated(_) => "Already seated",
+            RoomError::NotSeated => "Not seated",
+            RoomError::Full => "Server full",
+        }
+    }
+}
+
+impl ResponseError for RoomError {
+    fn error_response(&self) -> HttpResponse {
+        let mut response = match self {
+            RoomError::NotFound(_) => HttpResponse::NotFound(),
+            RoomError::Expired(_) => HttpResponse::Gone(),
+            RoomError::Unauthorized => HttpResponse::Unauthorized(),
+            RoomError::SeatTaken(_) | RoomError::AlreadySeated(_) => HttpResponse::Conflict(),
+            RoomError::NotSeated => HttpResponse::Forbidden(),
+            RoomError::Full => HttpResponse::ServiceUnavailable(),
+        };
+        response.json(self.body())
+    }
+}
+
+// Extracts the room named by the `{room}` segment of the request path
+impl FromRequest for Room {
+    type Error = RoomError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let code = req.match_info().get("room").unwrap_or_default();
+        let rooms = req
+            .app_data::<web::Data<RoomRegistry>>()
+            .expect("RoomRegistry is registered as app data");
+        ready(rooms.find(code))
+    }
+}
+
+impl FromRequest for SpymasterRoom {
+    type Error = RoomError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        ready(
+            Room::from_request(req, payload)
+                .into_inner()
+                .and_then(|room| {
+                    room.authorize_spymaster(req)?;
+                    Ok(SpymasterRoom(room))
+                }),
+        )
+    }
+}
+
+impl FromRequest for SeatedSpymaster {
+    type Error = RoomError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        ready(
+            Room::from_request(req, payload)
+                .into_inner()
+                .and_then(|room| {
+                    let team = seat_token(req)
+                        .and_then(|token| room.seat_team(&token))
+                        .ok_or(RoomError::NotSeated)?;
+                    Ok(SeatedSpymaster { room, team })
+                }),
+        )
+    }
+}
+
+// Shared state for looking up rooms by code
+#[derive(Debug, Clone)]
+pub struct RoomRegistry {
+    rooms: Arc<Mutex<HashMap<String, Room>>>,
+    expired: Arc<Mutex<HashMap<String, Instant>>>,
+    config: GameConfig,
+    word_packs: WordPacks,
+}
+
+impl RoomRegistry {
+    pub fn new(config: GameConfig, word_packs: WordPacks) -> Self {
+        Self {
+            rooms: Arc::new(Mutex::new(HashMap::new())),
+            expired: Arc::new(Mutex::new(HashMap::new())),
+            config,
+            word_packs,
+        }
+    }
+
+    /// Opens a room with a fresh code, unless there are too many open already or no free code
+    /// turns up
+    pub fn create(&self) -> Result<Room, RoomError> {
+        let mut rooms = self.rooms.lock().unwrap();
+        let expired = self.expired.lock().unwrap();
+        if rooms.len() >= MAX_ROOMS {
+            return Err(RoomError::Full);
+        }
+
+        let code = (0..CODE_ATTEMPTS)
+            .map(|_| Self::random_code())
+            .find(|code| !rooms.contains_key(code) && !expired.contains_key(code))
+            .ok_or(RoomError::Full)?;
+
+        let room = Room {
+            code: code.clone(),
+            game: GameState::with_config(
+                Turn::Blue,
+                self.config,
+                &self.word_packs.default_source(),
+            ),
+            ws: WsState::new(),
+            players: Roster::new(),
+            word_packs: self.word_packs.clone(),
+            spymaster_token: random_token(),
+            seats: Arc::new(Mutex::new(HashMap::new())),
+            seats_revision: Arc::new(AtomicU64::new(0)),
+            last_activity: Arc::new(Mutex::new(Instant::now())),
+        };
+        rooms.insert(code, room.clone());
+        Ok(room)
+    }
+
+    /// The word packs new games can be dealt from
+    pub fn word_packs(&self) -> &WordPacks {
+        &self.word_packs
+    }
+
+    /// Brings back rooms saved before a restart, returning how many could be restored
+    pub fn restore(&self, saved: Vec<SavedRoom>) -> usize {
+        let mut rooms = self.rooms.lock().unwrap();
+        let mut restored = 0;
+
+        for saved in saved {
+            let Some(game) = GameState::from_events(saved.events, self.config) else {
+                log::warn!("Skipping saved room {} with no board", saved.code);
+                continue;
+            };
+            let room = Room {
+                code: saved.code.clone(),
+                game,
+                ws: WsState::resume(saved.seq),
+                players: Roster::restore(saved.players),
+                word_packs: self.word_packs.clone(),
+                spymaster_token: saved.spymaster_token,
+                seats: Arc::new(Mutex::new(saved.seats)),
+                seats_revision: Arc::new(AtomicU64::new(0)),
+                last_activity: Arc::new(Mutex::new(Instant::now())),
+            };
+            rooms.insert(saved.code, room);
+            restored += 1;
+        }
+
+        restored
+    }
+
+    /// Looks up a room and marks it as in use; codes are case-insensitive
+    pub fn find(&self, code: &str) -> Result<Room, RoomError> {
+        let code = code.to_uppercase();
+        if let Some(room) = self.rooms.lock().unwrap().get(&code) {
+            room.touch();
+            return Ok(room.clone());
+        }
+
+        if self.expired.lock().unwrap().contains_key(&code) {
+            Err(RoomError::Expired(code))
+        } else {
+            Err(RoomError::NotFound(code))
+        }
+    }
+
+    pub fn all(&self) -> Vec<Room> {
+        self.rooms.lock().unwrap().values().cloned().collect()
+    }
+
+    /// Removes rooms that have had no connections and no activity for `ttl`, returning their codes
+    pub fn evict_idle(&self, ttl: Duration) -> Vec<String> {
+        let mut rooms = self.rooms.lock().unwrap();
+        let mut expired = self.expired.lock().unwrap();
+
+        let evicted: Vec<_> = rooms
+            .values()
+            .filter(|room| room.ws.connection_count() == 0 && room.idle_time() > ttl)
+            .map(|room| room.code.clone())
+            .collect();
+
+        let now = Instant::now();
+        for code in &evicted {
+            rooms.remove(code);
+            expired.insert(code.clone(), now);
+        }
+        expired.retain(|_, at| now.duration_since(*at) < EXPIRED_MEMORY);
+
+        evicted
+    }
+
+    fn random_code() -> String {
+        let mut rng = rand::rng();
+        (0..CODE_LENGTH)
+            .map(|_| CODE_ALPHABET[rng.random_range(0..CODE_ALPHABET.len())] as char)
+            .collect()
+    }
+}
+
+// Background task function
+pub async fn room_cleanup_task(rooms: web::Data<RoomRegistry>, ttl: Duration) {
+    let mut interval = tokio::time::interval(REAP_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        for room in rooms.all() {
+            for player in room.players.abandoned(SESSION_GRACE) {
+                // The player may have left in the meantime
+                let _ = api::leave(player, &room);
+            }
+        }
+
+        let evicted = rooms.evict_idle(ttl);
+        if !evicted.is_empty() {
+            log::info!(
+                "Expired {} idle rooms: {}",
+                evicted.len(),
+                evicted.join(", ")
+            );
+        }
+    }
+}
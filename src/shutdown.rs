@@ -0,0 +1,55 @@
+use std::future::{Future, poll_fn};
+use std::pin::pin;
+use std::task::Poll;
+use std::time::Duration;
+
+use actix_web::rt::signal::ctrl_c;
+
+use crate::room::RoomRegistry;
+use crate::storage::Storage;
+
+/// How long clients are told to wait before reconnecting, long enough for a restart to come back up
+const RECONNECT_AFTER: Duration = Duration::from_secs(5);
+
+/// Resolves once the process is asked to stop, by SIGTERM or Ctrl-C
+pub async fn signal() {
+    #[cfg(unix)]
+    {
+        use actix_web::rt::signal::unix::{SignalKind, signal};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                let mut interrupt = pin!(ctrl_c());
+                poll_fn(|cx| {
+                    if terminate.poll_recv(cx).is_ready() || interrupt.as_mut().poll(cx).is_ready()
+                    {
+                        Poll::Ready(())
+                    } else {
+                        Poll::Pending
+                    }
+                })
+                .await;
+                return;
+            }
+            Err(err) => log::warn!("Can't listen for SIGTERM: {err}"),
+        }
+    }
+
+    if let Err(err) = ctrl_c().await {
+        log::warn!("Can't listen for Ctrl-C: {err}");
+        std::future::pending::<()>().await;
+    }
+}
+
+/// Saves every room, then tells their clients the server is going away and disconnects them
+pub fn shut_down(rooms: &RoomRegistry, storage: Option<&Storage>) {
+    if let Some(storage) = storage
+        && let Err(err) = storage.save(rooms)
+    {
+        log::error!("Failed to save rooms before shutting down: {err}");
+    }
+
+    for room in rooms.all() {
+        room.ws.shut_down(RECONNECT_AFTER);
+    }
+}
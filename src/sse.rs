@@ -0,0 +1,165 @@
+use actix_web::body::{BodySize, MessageBody};
+use actix_web::http::header;
+use actix_web::web::Bytes;
+use actix_web::{HttpRequest, HttpResponse};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+use crate::protocol::ProtocolVersion;
+use crate::room::{Room, SpymasterRoom};
+use crate::websocket::{self, ClientType, ConnectParams, Sequenced, WsMessage, WsState};
+
+/// How many events may wait for a slow stream before it is closed and left to catch up
+const STREAM_BUFFER: usize = 64;
+const LAST_EVENT_ID_HEADER: &str = "Last-Event-ID";
+
+/// What the room's broadcasts push to an event stream
+#[derive(Debug)]
+pub enum SseFrame {
+    Event(Sequenced),
+    KeepAlive,
+    /// Ends the stream, telling the browser how long to wait before reconnecting
+    Shutdown {
+        message: WsMessage,
+        reconnect_after: Duration,
+    },
+}
+
+// Body of a `text/event-stream` response: the initial snapshot or replay, then live events
+struct EventStream {
+    id: u64,
+    ws: WsState,
+    pending: VecDeque<Bytes>,
+    receiver: mpsc::Receiver<SseFrame>,
+    /// Sequence number of the latest event sent, so events already replayed aren't sent twice
+    last_seq: u64,
+    protocol: ProtocolVersion,
+    /// Set once the server is shutting down; the stream ends when what's pending has been sent
+    closing: bool,
+}
+
+impl EventStream {
+    fn push(&mut self, seq: u64, message: &impl Serialize) {
+        if let Ok(json) = self.protocol.encode(message) {
+            self.pending
+                .push_back(Bytes::from(format!("id: {seq}\ndata: {json}\n\n")));
+        }
+        self.last_seq = self.last_seq.max(seq);
+    }
+}
+
+impl MessageBody for EventStream {
+    type Error = Infallible;
+
+    fn size(&self) -> BodySize {
+        BodySize::Stream
+    }
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, Self::Error>>> {
+        let this = self.get_mut();
+        loop {
+            if let Some(bytes) = this.pending.pop_front() {
+                return Poll::Ready(Some(Ok(bytes)));
+            }
+            if this.closing {
+                return Poll::Ready(None);
+            }
+
+            match this.receiver.poll_recv(cx) {
+                Poll::Ready(Some(SseFrame::Event(event))) => {
+                    if event.seq > this.last_seq {
+                        this.push(event.seq, &event);
+                    }
+                }
+                Poll::Ready(Some(SseFrame::KeepAlive)) => {
+                    return Poll::Ready(Some(Ok(Bytes::from_static(b": keep-alive\n\n"))));
+                }
+                Poll::Ready(Some(SseFrame::Shutdown {
+                    message,
+                    reconnect_after,
+                })) => {
+                    if let Ok(json) = this.protocol.encode(&message) {
+                        let retry = reconnect_after.as_millis();
+                        this.pending
+                            .push_back(Bytes::from(format!("retry: {retry}\ndata: {json}\n\n")));
+                    }
+                    this.closing = true;
+                }
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+impl Drop for EventStream {
+    fn drop(&mut self) {
+        self.ws.remove_connection(&self.id);
+    }
+}
+
+fn start_stream(client_type: ClientType, room: Room, req: &HttpRequest) -> HttpResponse {
+    let params = ConnectParams::from_request(req);
+    // Browsers resend the id of the last event they saw when they reconnect
+    let last_seq = req
+        .headers()
+        .get(LAST_EVENT_ID_HEADER)
+        .and_then(|value| value.to_str().ok()?.parse().ok())
+        .or(params.last_seq);
+
+    let (sender, receiver) = mpsc::channel(STREAM_BUFFER);
+    let id = room.ws.add_event_stream(client_type, sender);
+    let mut stream = EventStream {
+        id,
+        ws: room.ws.clone(),
+        pending: VecDeque::new(),
+        receiver,
+        last_seq: 0,
+        protocol: params.protocol(),
+        closing: false,
+    };
+
+    // Registered first, so anything broadcast meanwhile is queued rather than missed
+    match last_seq.and_then(|last_seq| room.ws.replay(last_seq, client_type)) {
+        Some(events) => {
+            stream.last_seq = last_seq.unwrap_or_default();
+            for event in &events {
+                stream.push(event.seq, event);
+            }
+        }
+        None => {
+            let (seq, snapshot) = websocket::snapshot(&room, client_type);
+            stream.push(seq, &snapshot);
+        }
+    }
+
+    log::info!(
+        "Event stream {} opened for room {} ({:?})",
+        id,
+        room.code,
+        client_type
+    );
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        // Stops nginx-style proxies from buffering the stream
+        .insert_header(("X-Accel-Buffering", "no"))
+        .body(stream)
+}
+
+// HTTP endpoints streaming a room's events
+pub async fn get_public(req: HttpRequest, room: Room) -> HttpResponse {
+    start_stream(ClientType::Public, room, &req)
+}
+
+pub async fn get_spymaster(req: HttpRequest, SpymasterRoom(room): SpymasterRoom) -> HttpResponse {
+    start_stream(ClientType::Spymaster, room, &req)
+}
@@ -0,0 +1,132 @@
+use actix_web::web;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::game::{RecordedEvent, Turn};
+use crate::player::Member;
+use crate::room::RoomRegistry;
+
+/// How often rooms are checked for changes that need saving
+const SAVE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Everything needed to bring a room back after a restart
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SavedRoom {
+    pub code: String,
+    pub spymaster_token: String,
+    pub seats: HashMap<Turn, String>,
+    pub players: Vec<Member>,
+    /// The game's event log, from which the board, turn and history are rebuilt
+    pub events: Vec<RecordedEvent>,
+    /// Sequence number of the last event sent to clients
+    pub seq: u64,
+}
+
+/// A word pack uploaded through the API
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SavedWordPack {
+    pub name: String,
+    pub words: Vec<String>,
+}
+
+/// Everything in the data file
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SavedData {
+    pub rooms: Vec<SavedRoom>,
+    #[serde(default)]
+    pub word_packs: Vec<SavedWordPack>,
+}
+
+// The revision of everything in the data file, which is much cheaper to compare than the data
+#[derive(Debug, PartialEq)]
+struct Revisions {
+    rooms: Vec<(String, u64)>,
+    word_packs: u64,
+}
+
+impl Revisions {
+    fn of(registry: &RoomRegistry) -> Self {
+        let mut rooms: Vec<_> = registry
+            .all()
+            .iter()
+            .map(|room| (room.code.clone(), room.revision()))
+            .collect();
+        rooms.sort();
+        Self {
+            rooms,
+            word_packs: registry.word_packs().revision(),
+        }
+    }
+}
+
+/// A JSON file that rooms and uploaded word packs are saved to and restored from
+#[derive(Debug, Clone)]
+pub struct Storage {
+    path: PathBuf,
+    // The revisions last written, so nothing is serialized again until something changes
+    last_saved: Arc<Mutex<Option<Revisions>>>,
+}
+
+impl Storage {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            last_saved: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Reads the saved rooms and word packs; a missing file means there are none yet
+    pub fn load(&self) -> io::Result<SavedData> {
+        let json = match std::fs::read_to_string(&self.path) {
+            Ok(json) => json,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(SavedData::default()),
+            Err(err) => return Err(err),
+        };
+        Ok(serde_json::from_str(&json)?)
+    }
+
+    /// Writes every room and uploaded word pack to disk if anything has changed since the last
+    /// save.
+    ///
+    /// The file is replaced in one step, so a crash mid-write leaves the previous save intact.
+    pub fn save(&self, registry: &RoomRegistry) -> io::Result<()> {
+        let mut last_saved = self.last_saved.lock().unwrap();
+        let revisions = Revisions::of(registry);
+        if last_saved.as_ref() == Some(&revisions) {
+            return Ok(());
+        }
+
+        let mut rooms: Vec<_> = registry.all().iter().map(|room| room.save()).collect();
+        rooms.sort_by(|a, b| a.code.cmp(&b.code));
+        let json = serde_json::to_string(&SavedData {
+            rooms,
+            word_packs: registry.word_packs().save(),
+        })?;
+
+        let temp = self.path.with_extension("tmp");
+        std::fs::write(&temp, &json)?;
+        std::fs::rename(&temp, &self.path)?;
+        *last_saved = Some(revisions);
+        Ok(())
+    }
+}
+
+// Background task that keeps the saved rooms up to date
+pub async fn storage_task(rooms: web::Data<RoomRegistry>, storage: Storage) {
+    let mut interval = tokio::time::interval(SAVE_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        // Serializing and writing every room can take a while, so it's kept off the runtime
+        let (task_storage, task_rooms) = (storage.clone(), rooms.clone());
+        let saved = web::block(move || task_storage.save(&task_rooms)).await;
+        if let Err(err) = saved.map_err(io::Error::other).and_then(|saved| saved) {
+            log::error!("Failed to save rooms to {}: {err}", storage.path.display());
+        }
+    }
+}
@@ -0,0 +1,239 @@
+use serde::Serialize;
+
+use crate::game::Board;
+
+/// A rule that a clue broke
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "rule", rename_all = "snake_case")]
+pub enum ClueError {
+    Empty,
+    MultipleWords,
+    /// The clue is the word on an unrevealed card
+    OnBoard {
+        word: String,
+    },
+    /// The clue contains the word on an unrevealed card
+    ContainsBoardWord {
+        word: String,
+    },
+    /// The clue is part of the word on an unrevealed card
+    PartOfBoardWord {
+        word: String,
+    },
+    /// The clue is a plural or other simple inflection of the word on an unrevealed card
+    FormOfBoardWord {
+        word: String,
+    },
+}
+
+impl std::fmt::Display for ClueError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ClueError::Empty => write!(f, "The clue must not be empty."),
+            ClueError::MultipleWords => write!(f, "The clue must be a single word."),
+            ClueError::OnBoard { word } => write!(f, "\"{word}\" is on the board."),
+            ClueError::ContainsBoardWord { word } => {
+                write!(f, "The clue contains \"{word}\", which is on the board.")
+            }
+            ClueError::PartOfBoardWord { word } => {
+                write!(f, "The clue is part of \"{word}\", which is on the board.")
+            }
+            ClueError::FormOfBoardWord { word } => {
+                write!(
+                    f,
+                    "The clue is a form of \"{word}\", which is on the board."
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for ClueError {}
+
+/// Checks a clue against the unrevealed cards on the board.
+///
+/// With `lenient` set (house rules), only the empty clue and the exact word on an unrevealed card
+/// are refused.
+pub fn validate_clue(clue: &str, board: &Board, lenient: bool) -> Result<(), ClueError> {
+    let clue = clue.trim().to_lowercase();
+    if clue.is_empty() {
+        return Err(ClueError::Empty);
+    }
+    if !lenient && clue.split_whitespace().count() > 1 {
+        return Err(ClueError::MultipleWords);
+    }
+
+    for card in board.iter().flatten().filter(|card| !card.revealed) {
+        let word = card.word.to_lowercase();
+        let error = if clue == word {
+            Some(ClueError::OnBoard {
+                word: card.word.clone(),
+            })
+        } else if lenient {
+            None
+        } else if clue.contains(&word) {
+            Some(ClueError::ContainsBoardWord {
+                word: card.word.clone(),
+            })
+        } else if word.contains(&clue) {
+            Some(ClueError::PartOfBoardWord {
+                word: card.word.clone(),
+            })
+        } else if same_stem(&clue, &word) {
+            Some(ClueError::FormOfBoardWord {
+                word: card.word.clone(),
+            })
+        } else {
+            None
+        };
+
+        if let Some(error) = error {
+            return Err(error);
+        }
+    }
+
+    Ok(())
+}
+
+/// The word along with every base it could be an inflection of, so that e.g. "berries" and "berry"
+/// or "spying" and "spies" share one. Only suffixes that inflect nouns and verbs alike are
+/// stripped: "er" is left alone, since "tower" isn't a form of "tow".
+fn stems(word: &str) -> Vec<String> {
+    // "ies" and "ied" come from a "y", which may follow a very short base, as in "spies"
+    const SUFFIXES: [(&str, &str, usize); 6] = [
+        ("ies", "y", 1),
+        ("ied", "y", 1),
+        ("ing", "", 3),
+        ("es", "", 3),
+        ("ed", "", 3),
+        ("s", "", 3),
+    ];
+
+    let mut stems = vec![word.to_string()];
+    for (suffix, replacement, min_base) in SUFFIXES {
+        if let Some(base) = word.strip_suffix(suffix)
+            && base.len() >= min_base
+        {
+            stems.push(format!("{base}{replacement}"));
+        }
+    }
+    stems
+}
+
+fn same_stem(a: &str, b: &str) -> bool {
+    let b = stems(b);
+    stems(a).iter().any(|stem| b.contains(stem))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{Card, Team};
+
+    // A board with `words` first and filler after them, none of them revealed
+    fn board(words: &[&str]) -> Board {
+        std::array::from_fn(|row| {
+            std::array::from_fn(|col| {
+                let index = row * 5 + col;
+                Card {
+                    word: words
+                        .get(index)
+                        .map_or_else(|| format!("Q{index}"), |word| word.to_string()),
+                    team: Team::Neutral,
+                    revealed: false,
+                }
+            })
+        })
+    }
+
+    #[test]
+    fn refuses_the_word_on_a_card() {
+        let board = board(&["Tower"]);
+        assert_eq!(
+            validate_clue(" tower ", &board, false),
+            Err(ClueError::OnBoard {
+                word: "Tower".into()
+            })
+        );
+    }
+
+    #[test]
+    fn allows_the_word_on_a_revealed_card() {
+        let mut board = board(&["Tower"]);
+        board[0][0].revealed = true;
+        assert_eq!(validate_clue("tower", &board, false), Ok(()));
+    }
+
+    #[test]
+    fn refuses_clues_containing_a_board_word() {
+        assert_eq!(
+            validate_clue("firetower", &board(&["Tower"]), false),
+            Err(ClueError::ContainsBoardWord {
+                word: "Tower".into()
+            })
+        );
+    }
+
+    #[test]
+    fn refuses_clues_inside_a_board_word() {
+        assert_eq!(
+            validate_clue("moon", &board(&["Honeymoon"]), false),
+            Err(ClueError::PartOfBoardWord {
+                word: "Honeymoon".into()
+            })
+        );
+    }
+
+    #[test]
+    fn refuses_inflections_of_a_board_word() {
+        for (clue, word) in [
+            ("spies", "Spy"),
+            ("spied", "Spy"),
+            ("berry", "Berries"),
+            ("carried", "Carry"),
+            ("spying", "Spies"),
+        ] {
+            assert_eq!(
+                validate_clue(clue, &board(&[word]), false),
+                Err(ClueError::FormOfBoardWord {
+                    word: word.to_string()
+                }),
+                "{clue} for {word}"
+            );
+        }
+    }
+
+    #[test]
+    fn allows_words_that_only_look_like_inflections() {
+        for (clue, word) in [("towing", "Tower"), ("dressing", "Dresser")] {
+            assert_eq!(
+                validate_clue(clue, &board(&[word]), false),
+                Ok(()),
+                "{clue} for {word}"
+            );
+        }
+    }
+
+    #[test]
+    fn refuses_empty_and_multi_word_clues() {
+        let board = board(&["Tower"]);
+        assert_eq!(validate_clue("  ", &board, false), Err(ClueError::Empty));
+        assert_eq!(
+            validate_clue("ice cream", &board, false),
+            Err(ClueError::MultipleWords)
+        );
+    }
+
+    #[test]
+    fn lenient_clues_only_refuse_the_exact_word() {
+        let board = board(&["Tower", "Spy"]);
+        assert_eq!(validate_clue("ice cream", &board, true), Ok(()));
+        assert_eq!(validate_clue("firetower", &board, true), Ok(()));
+        assert_eq!(validate_clue("spies", &board, true), Ok(()));
+        assert_eq!(validate_clue("", &board, true), Err(ClueError::Empty));
+        assert_eq!(
+            validate_clue("Spy", &board, true),
+            Err(ClueError::OnBoard { word: "Spy".into() })
+        );
+    }
+}
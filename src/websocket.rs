@@ -1,42 +1,258 @@
+use crate::api::{
+    self, ApiError, ChatParams, ClueParams, EndTurnParams, JoinParams, NewGameParams, RevealParams,
+};
+use crate::game::{Card, Clue, GameOverReason, Turn, UndoVotes};
+use crate::player::{Player, PlayerError, PlayerId};
+use crate::protocol::ProtocolVersion;
+use crate::room::{Room, RoomError, RoomRegistry, SpymasterRoom};
+use crate::sse::SseFrame;
 use actix::prelude::*;
 use actix_web::{HttpRequest, HttpResponse, Result, web};
 use actix_web_actors::ws;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, MutexGuard};
 use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
 
 const HEARTBEAT: Duration = Duration::from_secs(10);
 const TIMEOUT: Duration = Duration::from_secs(20);
+/// How many past events each room keeps for clients catching up after a gap or a reconnect
+const REPLAY_CAPACITY: usize = 256;
 
 // WebSocket message types
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum WsMessage {
+    Hello { data: HelloData },
+    Snapshot { data: serde_json::Value },
     CardRevealed { data: CardRevealData },
+    CardHidden { data: CardHiddenData },
+    UndoVote { data: UndoVotes },
     NewGame { data: serde_json::Value },
+    TurnChanged { data: TurnChangeData },
+    GameOver { data: GameOverData },
+    ClueGiven { data: Clue },
+    TurnPassed { data: TurnPassData },
+    RoomExpired { data: RoomExpiredData },
+    ServerShuttingDown { data: ShutdownData },
+    SeatsChanged { data: serde_json::Value },
+    PlayerJoined { data: Player },
+    PlayerLeft { data: Player },
+    PlayerChangedTeam { data: Player },
+    PlayerConnected { data: Player },
+    PlayerDisconnected { data: Player },
+    Chat { data: ChatMessage },
+    Ack { data: AckData },
+    Error { data: ErrorData },
+}
+
+// Commands sent by clients over the WebSocket
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientCommand {
+    Reveal {
+        data: RevealParams,
+    },
+    NewGame {
+        #[serde(default)]
+        data: NewGameParams,
+    },
+    GiveClue {
+        data: ClueParams,
+    },
+    EndTurn {
+        data: EndTurnParams,
+    },
+    Join {
+        data: JoinParams,
+    },
+    Chat {
+        data: ChatParams,
+    },
+    Resync {
+        data: ResyncParams,
+    },
+    Hello {
+        data: HelloParams,
+    },
+    Undo,
+    VoteUndo,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct HelloParams {
+    /// The protocol version the client speaks
+    pub version: u32,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResyncParams {
+    /// Sequence number of the last event the client applied
+    pub last_seq: u64,
+}
+
+/// A command along with the id the client chose for it, which is echoed in the reply
+#[derive(Debug, Deserialize)]
+pub struct ClientRequest {
+    pub id: Option<String>,
+    #[serde(flatten)]
+    pub command: ClientCommand,
+}
+
+/// A game event as sent to a client, numbered so that missed events can be noticed
+#[derive(Debug, Clone, Serialize)]
+pub struct Sequenced {
+    pub seq: u64,
+    #[serde(flatten)]
+    pub message: WsMessage,
+}
+
+// A broadcast, with the message each kind of client receives for it
+#[derive(Debug, Clone)]
+struct Event {
+    seq: u64,
+    messages: Vec<(WsMessage, Option<ClientType>)>,
+}
+
+impl Event {
+    fn message_for(&self, client_type: ClientType) -> Option<Sequenced> {
+        self.messages
+            .iter()
+            .find(|(_, target)| target.is_none_or(|target| target == client_type))
+            .map(|(message, _)| Sequenced {
+                seq: self.seq,
+                message: message.clone(),
+            })
+    }
+}
+
+// The latest sequence number and the events that can still be replayed
+#[derive(Debug, Default)]
+struct EventLog {
+    seq: u64,
+    recent: VecDeque<Event>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CardRevealData {
     pub row: usize,
     pub col: usize,
-    pub new_card_state: serde_json::Value,
+    pub new_card_state: Card,
+}
+
+/// A card whose reveal was undone, with the recipient's corrected view of the game
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CardHiddenData {
+    pub row: usize,
+    pub col: usize,
+    pub game: serde_json::Value,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TurnChangeData {
+    pub turn: Turn,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TurnPassData {
+    pub team: Turn,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GameOverData {
+    pub winner: Turn,
+    pub reason: GameOverReason,
+    pub key: serde_json::Value,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoomExpiredData {
+    pub code: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ShutdownData {
+    /// How long clients should wait before reconnecting
+    pub reconnect_after_ms: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub player: Player,
+    pub text: String,
+}
+
+/// The protocol version the server speaks with a client
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HelloData {
+    pub version: u32,
+    pub supported: Vec<u32>,
+}
+
+/// Reply to a command that succeeded
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AckData {
+    pub id: Option<String>,
+    pub result: serde_json::Value,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ErrorData {
+    /// Id of the command that failed, if the error is a reply to one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// The same body an HTTP request failing this way gets back
+    #[serde(flatten)]
+    pub body: serde_json::Value,
+}
+
+impl<E: ApiError> From<E> for ErrorData {
+    fn from(err: E) -> Self {
+        Self {
+            id: None,
+            body: err.body(),
+        }
+    }
+}
+
+/// A WebSocket message that isn't a command this server knows
+struct InvalidCommand(serde_json::Error);
+
+impl std::fmt::Display for InvalidCommand {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl ApiError for InvalidCommand {
+    fn kind(&self) -> &'static str {
+        "Invalid command"
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum ClientType {
     Public,
+    /// A spymaster, whichever seat they hold; the seat is looked up from their seat token when it
+    /// matters, since it can change while they are connected
     Spymaster,
 }
 
+// Where a connection's messages are delivered
+#[derive(Debug, Clone)]
+enum Outlet {
+    WebSocket(actix::Addr<WebSocketSession>),
+    EventStream(mpsc::Sender<SseFrame>),
+}
+
 // Connection info stored in WsState
 #[derive(Debug, Clone)]
 struct ConnectionInfo {
     client_type: ClientType,
     last_pong: Instant,
-    addr: actix::Addr<WebSocketSession>,
+    outlet: Outlet,
 }
 
 // Shared state for managing connections
@@ -44,6 +260,7 @@ struct ConnectionInfo {
 pub struct WsState {
     connections: Arc<Mutex<HashMap<u64, ConnectionInfo>>>,
     next_id: Arc<AtomicU64>,
+    events: Arc<Mutex<EventLog>>,
 }
 
 impl WsState {
@@ -51,9 +268,18 @@ impl WsState {
         Self {
             connections: Arc::new(Mutex::new(HashMap::new())),
             next_id: Arc::new(AtomicU64::new(1)),
+            events: Arc::new(Mutex::new(EventLog::default())),
         }
     }
 
+    /// Continues numbering events from before a restart. One number is skipped, so no client can
+    /// be mistaken for being up to date with the restored state; they are all sent a snapshot
+    pub fn resume(seq: u64) -> Self {
+        let state = Self::new();
+        state.events.lock().unwrap().seq = seq + 1;
+        state
+    }
+
     pub fn add_connection(
         &self,
         id: u64,
@@ -66,11 +292,26 @@ impl WsState {
             ConnectionInfo {
                 client_type,
                 last_pong: Instant::now(),
-                addr,
+                outlet: Outlet::WebSocket(addr),
             },
         );
     }
 
+    /// Registers a Server-Sent Events stream, returning its connection id
+    pub fn add_event_stream(&self, client_type: ClientType, sender: mpsc::Sender<SseFrame>) -> u64 {
+        let id = self.next_connection_id();
+        let mut connections = self.connections.lock().unwrap();
+        connections.insert(
+            id,
+            ConnectionInfo {
+                client_type,
+                last_pong: Instant::now(),
+                outlet: Outlet::EventStream(sender),
+            },
+        );
+        id
+    }
+
     pub fn remove_connection(&self, id: &u64) {
         let mut connections = self.connections.lock().unwrap();
         connections.remove(id);
@@ -87,21 +328,49 @@ impl WsState {
         self.next_id.fetch_add(1, Ordering::SeqCst)
     }
 
+    /// Locks the room's event numbering. Hold it from before changing the room until the change
+    /// has been announced, so that a snapshot never shows a change whose event it doesn't count.
+    pub fn lock_events(&self) -> EventLock<'_> {
+        EventLock {
+            events: self.events.lock().unwrap(),
+            connections: &self.connections,
+        }
+    }
+
     pub fn broadcast(&self, message: (WsMessage, Option<ClientType>)) {
-        let (msg, target_type) = message;
-        let connections = self.connections.lock().unwrap();
+        self.lock_events().broadcast(message);
+    }
 
-        for info in connections.values() {
-            if let Some(target) = target_type {
-                if info.client_type != target {
-                    continue;
-                }
-            }
+    /// Sequence number of the latest event
+    pub fn last_seq(&self) -> u64 {
+        self.events.lock().unwrap().seq
+    }
 
-            if let Err(e) = info.addr.try_send(BroadcastMessage(msg.clone())) {
-                log::warn!("Failed to send message to client: {:?}", e);
-            }
+    /// The events after `last_seq` as `client_type` would have received them, or `None` if some
+    /// of them are no longer kept
+    pub fn replay(&self, last_seq: u64, client_type: ClientType) -> Option<Vec<Sequenced>> {
+        let events = self.events.lock().unwrap();
+        if last_seq > events.seq {
+            // From before a restart, or otherwise not ours
+            return None;
+        }
+        if last_seq < events.seq
+            && events
+                .recent
+                .front()
+                .is_none_or(|event| event.seq > last_seq + 1)
+        {
+            return None;
         }
+
+        Some(
+            events
+                .recent
+                .iter()
+                .filter(|event| event.seq > last_seq)
+                .filter_map(|event| event.message_for(client_type))
+                .collect(),
+        )
     }
 
     // Cleanup stale connections and ping active ones
@@ -113,12 +382,19 @@ impl WsState {
             let mut connections = self.connections.lock().unwrap();
             let now = Instant::now();
 
-            // Find connections to remove (no pong for 60 seconds) and ping active ones
+            // Find connections to remove (no pong for 60 seconds, or a closed event stream) and
+            // ping active ones
             for (id, info) in connections.iter() {
-                if now.duration_since(info.last_pong) > Duration::from_secs(60) {
+                let stale = match &info.outlet {
+                    Outlet::WebSocket(_) => {
+                        now.duration_since(info.last_pong) > Duration::from_secs(60)
+                    }
+                    Outlet::EventStream(sender) => sender.is_closed(),
+                };
+                if stale {
                     to_remove.push(*id);
                 } else {
-                    to_ping.push(info.addr.clone());
+                    to_ping.push(info.outlet.clone());
                 }
             }
 
@@ -129,13 +405,46 @@ impl WsState {
         }
 
         if !to_remove.is_empty() {
-            log::info!("Cleaned up {} stale WebSocket connections", to_remove.len());
+            log::info!("Cleaned up {} stale connections", to_remove.len());
         }
 
         // Send ping to active connections
-        for addr in to_ping {
-            if let Err(e) = addr.try_send(SendPing) {
-                log::warn!("Failed to ping client: {:?}", e);
+        for outlet in to_ping {
+            match outlet {
+                Outlet::WebSocket(addr) => {
+                    if let Err(e) = addr.try_send(SendPing) {
+                        log::warn!("Failed to ping client: {:?}", e);
+                    }
+                }
+                // Keeps proxies from timing out a quiet stream; a full stream is busy anyway
+                Outlet::EventStream(sender) => {
+                    let _ = sender.try_send(SseFrame::KeepAlive);
+                }
+            }
+        }
+    }
+
+    /// Tells every client that the server is going away and when to come back, then hangs up
+    pub fn shut_down(&self, reconnect_after: Duration) {
+        let message = WsMessage::ServerShuttingDown {
+            data: ShutdownData {
+                reconnect_after_ms: reconnect_after.as_millis() as u64,
+            },
+        };
+
+        for info in self.connections.lock().unwrap().values() {
+            match &info.outlet {
+                Outlet::WebSocket(addr) => {
+                    if let Err(e) = addr.try_send(Shutdown(message.clone())) {
+                        log::warn!("Failed to send shutdown to client: {:?}", e);
+                    }
+                }
+                Outlet::EventStream(sender) => {
+                    let _ = sender.try_send(SseFrame::Shutdown {
+                        message: message.clone(),
+                        reconnect_after,
+                    });
+                }
             }
         }
     }
@@ -145,34 +454,264 @@ impl WsState {
     }
 }
 
+/// A room's event numbering, locked by `WsState::lock_events`
+pub struct EventLock<'a> {
+    events: MutexGuard<'a, EventLog>,
+    connections: &'a Mutex<HashMap<u64, ConnectionInfo>>,
+}
+
+impl EventLock<'_> {
+    /// Sequence number of the latest event
+    pub fn seq(&self) -> u64 {
+        self.events.seq
+    }
+
+    pub fn broadcast(&mut self, message: (WsMessage, Option<ClientType>)) {
+        self.broadcast_event(vec![message]);
+    }
+
+    /// Sends one event, numbered with the next sequence number, to every client the messages are
+    /// addressed to. Each client gets the first message addressed to it, so every kind of client
+    /// should be covered to keep its sequence free of gaps.
+    pub fn broadcast_event(&mut self, messages: Vec<(WsMessage, Option<ClientType>)>) {
+        // The lock is held while sending, so clients receive events in sequence order
+        let events = &mut self.events;
+        events.seq += 1;
+        let event = Event {
+            seq: events.seq,
+            messages,
+        };
+
+        let mut connections = self.connections.lock().unwrap();
+        let mut overflowed = Vec::new();
+        for (id, info) in connections.iter() {
+            let Some(msg) = event.message_for(info.client_type) else {
+                continue;
+            };
+
+            match &info.outlet {
+                // A client that misses this will see the gap and ask for a replay
+                Outlet::WebSocket(addr) => {
+                    if let Err(e) = addr.try_send(BroadcastMessage(msg)) {
+                        log::warn!("Failed to send message to client: {:?}", e);
+                    }
+                }
+                // Closing a stream that can't keep up makes the browser reconnect with
+                // Last-Event-ID and catch up
+                Outlet::EventStream(sender) => {
+                    if sender.try_send(SseFrame::Event(msg)).is_err() {
+                        overflowed.push(*id);
+                    }
+                }
+            }
+        }
+        for id in &overflowed {
+            connections.remove(id);
+        }
+
+        if events.recent.len() == REPLAY_CAPACITY {
+            events.recent.pop_front();
+        }
+        events.recent.push_back(event);
+    }
+}
+
 // Actor messages
 #[derive(Message)]
 #[rtype(result = "()")]
-struct BroadcastMessage(WsMessage);
+struct BroadcastMessage(Sequenced);
 
 #[derive(Message)]
 #[rtype(result = "()")]
 struct SendPing;
 
+#[derive(Message)]
+#[rtype(result = "()")]
+struct Shutdown(WsMessage);
+
 // WebSocket session actor
 pub struct WebSocketSession {
     id: u64,
     client_type: ClientType,
-    ws_state: web::Data<WsState>,
+    room: Room,
+    seat_token: Option<String>,
+    /// The player whose session this connection resumes
+    player: Option<PlayerId>,
+    /// The last event a reconnecting client saw, to replay what it missed instead of a snapshot
+    last_seq: Option<u64>,
+    protocol: ProtocolVersion,
+    /// Whether the client asked for a protocol version when connecting, and so expects a hello
+    announce_protocol: bool,
     heartbeat: Instant,
 }
 
 impl WebSocketSession {
-    fn new(client_type: ClientType, ws_state: web::Data<WsState>) -> Self {
-        let id = ws_state.next_connection_id();
+    fn new(
+        client_type: ClientType,
+        room: Room,
+        seat_token: Option<String>,
+        player: Option<PlayerId>,
+        params: ConnectParams,
+    ) -> Self {
+        let id = room.ws.next_connection_id();
         Self {
             id,
             client_type,
-            ws_state,
+            room,
+            seat_token,
+            player,
+            last_seq: params.last_seq,
+            protocol: params.protocol(),
+            announce_protocol: params.protocol.is_some(),
             heartbeat: Instant::now(),
         }
     }
 
+    fn send(&self, msg: &impl Serialize, ctx: &mut ws::WebsocketContext<Self>) {
+        if let Ok(json) = self.protocol.encode(msg) {
+            ctx.text(json);
+        }
+    }
+
+    fn send_error(&self, data: ErrorData, ctx: &mut ws::WebsocketContext<Self>) {
+        self.send(&WsMessage::Error { data }, ctx);
+    }
+
+    fn handle_request(&mut self, text: &str, ctx: &mut ws::WebsocketContext<Self>) {
+        let request = match serde_json::from_str::<ClientRequest>(text) {
+            Ok(request) => request,
+            Err(e) => {
+                // Still correlate the error with the command if its id can be made out
+                let id = serde_json::from_str::<serde_json::Value>(text)
+                    .ok()
+                    .and_then(|value| value.get("id")?.as_str().map(str::to_string));
+                let data = ErrorData {
+                    id,
+                    ..InvalidCommand(e).into()
+                };
+                return self.send_error(data, ctx);
+            }
+        };
+
+        let reply = match self.handle_command(request.command, ctx) {
+            Ok(result) => WsMessage::Ack {
+                data: AckData {
+                    id: request.id,
+                    result,
+                },
+            },
+            Err(data) => WsMessage::Error {
+                data: ErrorData {
+                    id: request.id,
+                    ..data
+                },
+            },
+        };
+        self.send(&reply, ctx);
+    }
+
+    /// Runs a command through the same logic as the matching HTTP handler, returning the result
+    /// to acknowledge it with
+    fn handle_command(
+        &mut self,
+        command: ClientCommand,
+        ctx: &mut ws::WebsocketContext<Self>,
+    ) -> Result<serde_json::Value, ErrorData> {
+        match command {
+            ClientCommand::Reveal { data } => {
+                self.room.players.check_operative(self.player, data.team)?;
+                api::reveal(&data, self.player, &self.room)?;
+            }
+            ClientCommand::NewGame { data } => {
+                if self.client_type != ClientType::Spymaster {
+                    return Err(RoomError::Unauthorized.into());
+                }
+                let code = api::new_game(&data, &self.room)?;
+                return Ok(serde_json::json!({ "code": code }));
+            }
+            ClientCommand::GiveClue { data } => {
+                let team = self
+                    .seat_token
+                    .as_deref()
+                    .and_then(|token| self.room.seat_team(token))
+                    .ok_or(RoomError::NotSeated)?;
+                api::give_clue(team, &data, &self.room)?;
+            }
+            ClientCommand::EndTurn { data } => {
+                self.room.players.check_operative(self.player, data.team)?;
+                api::end_turn(&data, &self.room)?;
+            }
+            ClientCommand::Join { data } => {
+                if self.player.is_some() {
+                    return Err(PlayerError::AlreadyJoined.into());
+                }
+                let (player, session_token) = api::join(&data, &self.room)?;
+                // This connection now carries the new player's session
+                self.player = Some(player.id);
+                self.connect_player();
+                let player = self.room.players.get(player.id)?;
+                return Ok(serde_json::json!({
+                    "player": player,
+                    "session_token": session_token,
+                }));
+            }
+            ClientCommand::Chat { data } => {
+                let player = self.player.ok_or(PlayerError::InvalidSession)?;
+                api::chat(player, &data, &self.room)?;
+            }
+            ClientCommand::Undo => {
+                if self.client_type != ClientType::Spymaster {
+                    return Err(RoomError::Unauthorized.into());
+                }
+                api::undo(&self.room)?;
+            }
+            ClientCommand::VoteUndo => {
+                let player = self.player.ok_or(PlayerError::InvalidSession)?;
+                let votes = api::vote_undo(player, &self.room)?;
+                return Ok(serde_json::json!(votes));
+            }
+            ClientCommand::Resync { data } => self.catch_up(data.last_seq, ctx),
+            ClientCommand::Hello { data } => {
+                self.protocol = ProtocolVersion::negotiate(data.version);
+                self.send(&self.hello(), ctx);
+                return Ok(serde_json::json!({ "version": self.protocol.number() }));
+            }
+        }
+        Ok(serde_json::Value::Null)
+    }
+
+    fn hello(&self) -> WsMessage {
+        WsMessage::Hello {
+            data: HelloData {
+                version: self.protocol.number(),
+                supported: ProtocolVersion::SUPPORTED
+                    .iter()
+                    .map(|version| version.number())
+                    .collect(),
+            },
+        }
+    }
+
+    /// Sends the events after `last_seq`, or a snapshot if they are no longer all kept
+    fn catch_up(&self, last_seq: u64, ctx: &mut ws::WebsocketContext<Self>) {
+        match self.room.ws.replay(last_seq, self.client_type) {
+            Some(events) => {
+                for event in &events {
+                    self.send(event, ctx);
+                }
+            }
+            None => self.send(&snapshot(&self.room, self.client_type).1, ctx),
+        }
+    }
+
+    /// Marks the session's player as connected, announcing it if they were offline
+    fn connect_player(&self) {
+        let mut events = self.room.ws.lock_events();
+        if let Some(player) = self.player.and_then(|id| self.room.players.connect(id)) {
+            events.broadcast((WsMessage::PlayerConnected { data: player }, None));
+        }
+    }
+
     fn heartbeat(&self, ctx: &mut ws::WebsocketContext<Self>) {
         ctx.run_interval(HEARTBEAT, |act, ctx| {
             if Instant::now().duration_since(act.heartbeat) > TIMEOUT {
@@ -191,18 +730,38 @@ impl Actor for WebSocketSession {
 
     fn started(&mut self, ctx: &mut Self::Context) {
         self.heartbeat(ctx);
-        self.ws_state
+        self.room
+            .ws
             .add_connection(self.id, self.client_type, ctx.address());
+        if self.announce_protocol {
+            self.send(&self.hello(), ctx);
+        }
+        // Broadcasts are queued in the mailbox until this returns, so the snapshot always goes first
+        match self.last_seq {
+            Some(last_seq) => self.catch_up(last_seq, ctx),
+            None => self.send(&snapshot(&self.room, self.client_type).1, ctx),
+        }
+        self.connect_player();
         log::info!(
-            "WebSocket client {} connected ({:?})",
+            "WebSocket client {} connected to room {} ({:?})",
             self.id,
+            self.room.code,
             self.client_type
         );
     }
 
     fn stopped(&mut self, _ctx: &mut Self::Context) {
-        self.ws_state.remove_connection(&self.id);
-        log::info!("WebSocket client {} disconnected", self.id);
+        self.room.ws.remove_connection(&self.id);
+        // The player keeps their place for a grace period, in case they come back
+        let mut events = self.room.ws.lock_events();
+        if let Some(player) = self.player.and_then(|id| self.room.players.disconnect(id)) {
+            events.broadcast((WsMessage::PlayerDisconnected { data: player }, None));
+        }
+        log::info!(
+            "WebSocket client {} disconnected from room {}",
+            self.id,
+            self.room.code
+        );
     }
 }
 
@@ -210,9 +769,7 @@ impl Handler<BroadcastMessage> for WebSocketSession {
     type Result = ();
 
     fn handle(&mut self, msg: BroadcastMessage, ctx: &mut Self::Context) {
-        if let Ok(json) = serde_json::to_string(&msg.0) {
-            ctx.text(json);
-        }
+        self.send(&msg.0, ctx);
     }
 }
 
@@ -224,6 +781,19 @@ impl Handler<SendPing> for WebSocketSession {
     }
 }
 
+impl Handler<Shutdown> for WebSocketSession {
+    type Result = ();
+
+    fn handle(&mut self, msg: Shutdown, ctx: &mut Self::Context) {
+        self.send(&msg.0, ctx);
+        ctx.close(Some(ws::CloseReason {
+            code: ws::CloseCode::Restart,
+            description: Some("Server restarting".to_string()),
+        }));
+        ctx.stop();
+    }
+}
+
 impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WebSocketSession {
     fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
         match msg {
@@ -233,11 +803,13 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WebSocketSession
             }
             Ok(ws::Message::Pong(_)) => {
                 self.heartbeat = Instant::now();
-                self.ws_state.update_pong_time(&self.id);
+                self.room.ws.update_pong_time(&self.id);
             }
             Ok(ws::Message::Text(text)) => {
                 self.heartbeat = Instant::now();
                 log::info!("WebSocket client {} sent text: {:?}", self.id, text);
+                self.room.touch();
+                self.handle_request(&text, ctx);
             }
             Ok(ws::Message::Binary(_)) => {
                 log::warn!("Binary messages not supported");
@@ -255,36 +827,230 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WebSocketSession
     }
 }
 
+#[derive(Debug, Default, Deserialize)]
+pub struct ConnectParams {
+    pub last_seq: Option<u64>,
+    /// The protocol version the client speaks; clients that don't say are spoken to in v1
+    pub protocol: Option<u32>,
+}
+
+impl ConnectParams {
+    pub fn from_request(req: &HttpRequest) -> Self {
+        web::Query::<Self>::from_query(req.query_string())
+            .map(web::Query::into_inner)
+            .unwrap_or_default()
+    }
+
+    pub fn protocol(&self) -> ProtocolVersion {
+        self.protocol
+            .map_or(ProtocolVersion::V1, ProtocolVersion::negotiate)
+    }
+}
+
+/// Everything a client needs to render the game: its view of the board, turn and clue log, plus
+/// the seats and roster, along with the sequence number of the latest event it reflects
+pub fn snapshot(room: &Room, client_type: ClientType) -> (u64, WsMessage) {
+    // Changes are announced under this lock, so the state read below is exactly what the events up
+    // to `seq` describe
+    let events = room.ws.lock_events();
+    let seq = events.seq();
+    let mut data = match client_type {
+        ClientType::Public => room.game.public_json(),
+        ClientType::Spymaster => room.game.spymaster_json(),
+    };
+    data["seats"] = room.seats_json();
+    data["players"] = serde_json::json!(room.players.players());
+    data["seq"] = serde_json::json!(seq);
+    (seq, WsMessage::Snapshot { data })
+}
+
+// Short-lived session that tells a client its room has expired, then hangs up
+struct ExpiredSession {
+    code: String,
+}
+
+impl Actor for ExpiredSession {
+    type Context = ws::WebsocketContext<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        let msg = WsMessage::RoomExpired {
+            data: RoomExpiredData {
+                code: self.code.clone(),
+            },
+        };
+        if let Ok(json) = serde_json::to_string(&msg) {
+            ctx.text(json);
+        }
+        ctx.close(Some(ws::CloseReason {
+            code: ws::CloseCode::Normal,
+            description: Some("Room expired".to_string()),
+        }));
+        ctx.stop();
+    }
+}
+
+impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for ExpiredSession {
+    fn handle(&mut self, _msg: Result<ws::Message, ws::ProtocolError>, _ctx: &mut Self::Context) {}
+}
+
+fn start_session(
+    client_type: ClientType,
+    room: Result<Room, RoomError>,
+    req: &HttpRequest,
+    stream: web::Payload,
+) -> Result<HttpResponse> {
+    match room {
+        Ok(room) => {
+            let player = api::session_player(req, &room)?;
+            let seat_token = room.request_seat_token(req, player);
+            let params = ConnectParams::from_request(req);
+            let session = WebSocketSession::new(client_type, room, seat_token, player, params);
+            ws::start(session, req, stream)
+        }
+        Err(RoomError::Expired(code)) => ws::start(ExpiredSession { code }, req, stream),
+        Err(err) => Err(err.into()),
+    }
+}
+
 // HTTP endpoints to upgrade to WebSocket
 pub async fn get_public(
     req: HttpRequest,
     stream: web::Payload,
-    ws_state: web::Data<WsState>,
+    room: Result<Room, RoomError>,
 ) -> Result<HttpResponse> {
-    let session = WebSocketSession::new(ClientType::Public, ws_state);
-    ws::start(session, &req, stream)
+    start_session(ClientType::Public, room, &req, stream)
 }
 
 pub async fn get_spymaster(
     req: HttpRequest,
     stream: web::Payload,
-    ws_state: web::Data<WsState>,
+    room: Result<SpymasterRoom, RoomError>,
 ) -> Result<HttpResponse> {
-    let session = WebSocketSession::new(ClientType::Spymaster, ws_state);
-    ws::start(session, &req, stream)
+    let room = room.map(|SpymasterRoom(room)| room);
+    start_session(ClientType::Spymaster, room, &req, stream)
 }
 
 // Background task function
-pub async fn websocket_cleanup_task(ws_state: web::Data<WsState>) {
+pub async fn websocket_cleanup_task(rooms: web::Data<RoomRegistry>) {
     let mut interval = tokio::time::interval(HEARTBEAT);
 
     loop {
         interval.tick().await;
-        ws_state.cleanup_and_ping().await;
 
-        let count = ws_state.connection_count();
+        let mut count = 0;
+        for room in rooms.all() {
+            room.ws.cleanup_and_ping().await;
+            count += room.ws.connection_count();
+        }
+
         if count > 0 {
             log::debug!("Active WebSocket connections: {}", count);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Broadcasts `count` turn changes to every client
+    fn broadcast_turns(state: &WsState, count: usize) {
+        let mut events = state.lock_events();
+        for _ in 0..count {
+            let data = TurnChangeData { turn: Turn::Red };
+            events.broadcast((WsMessage::TurnChanged { data }, None));
+        }
+    }
+
+    fn seqs(replayed: Option<Vec<Sequenced>>) -> Option<Vec<u64>> {
+        replayed.map(|events| events.iter().map(|event| event.seq).collect())
+    }
+
+    #[test]
+    fn replays_the_events_after_last_seq() {
+        let state = WsState::new();
+        broadcast_turns(&state, 3);
+        assert_eq!(seqs(state.replay(1, ClientType::Public)), Some(vec![2, 3]));
+        assert_eq!(
+            seqs(state.replay(0, ClientType::Public)),
+            Some(vec![1, 2, 3])
+        );
+    }
+
+    #[test]
+    fn replays_nothing_to_an_up_to_date_client() {
+        let state = WsState::new();
+        assert_eq!(seqs(state.replay(0, ClientType::Public)), Some(vec![]));
+        broadcast_turns(&state, 2);
+        assert_eq!(seqs(state.replay(2, ClientType::Spymaster)), Some(vec![]));
+    }
+
+    #[test]
+    fn refuses_to_replay_from_a_future_seq() {
+        let state = WsState::new();
+        broadcast_turns(&state, 2);
+        assert!(state.replay(3, ClientType::Public).is_none());
+    }
+
+    #[test]
+    fn refuses_to_replay_from_before_a_restart() {
+        let state = WsState::resume(5);
+        // Even a client that saw the last event before the restart is missing the skipped one
+        assert!(state.replay(5, ClientType::Public).is_none());
+        assert!(state.replay(0, ClientType::Public).is_none());
+        assert_eq!(seqs(state.replay(6, ClientType::Public)), Some(vec![]));
+
+        broadcast_turns(&state, 1);
+        assert!(state.replay(5, ClientType::Public).is_none());
+        assert_eq!(seqs(state.replay(6, ClientType::Public)), Some(vec![7]));
+    }
+
+    #[test]
+    fn drops_the_oldest_event_once_full() {
+        let state = WsState::new();
+        broadcast_turns(&state, REPLAY_CAPACITY);
+        assert_eq!(
+            state.replay(0, ClientType::Public).unwrap().len(),
+            REPLAY_CAPACITY
+        );
+
+        broadcast_turns(&state, 1);
+        let last_seq = REPLAY_CAPACITY as u64 + 1;
+        assert_eq!(state.last_seq(), last_seq);
+        assert!(state.replay(0, ClientType::Public).is_none());
+        let replayed = seqs(state.replay(1, ClientType::Public)).unwrap();
+        assert_eq!(replayed.len(), REPLAY_CAPACITY);
+        assert_eq!(replayed.first(), Some(&2));
+        assert_eq!(replayed.last(), Some(&last_seq));
+    }
+
+    #[test]
+    fn replays_each_client_its_own_message() {
+        let state = WsState::new();
+        let turn = |turn| WsMessage::TurnChanged {
+            data: TurnChangeData { turn },
+        };
+        state.lock_events().broadcast_event(vec![
+            (turn(Turn::Blue), Some(ClientType::Spymaster)),
+            (turn(Turn::Red), None),
+        ]);
+        state.broadcast((turn(Turn::Blue), Some(ClientType::Spymaster)));
+
+        let turns = |client_type| {
+            state
+                .replay(0, client_type)
+                .unwrap()
+                .into_iter()
+                .map(|event| match event.message {
+                    WsMessage::TurnChanged { data } => (event.seq, data.turn),
+                    other => panic!("unexpected {other:?}"),
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(turns(ClientType::Public), vec![(1, Turn::Red)]);
+        assert_eq!(
+            turns(ClientType::Spymaster),
+            vec![(1, Turn::Blue), (2, Turn::Blue)]
+        );
+    }
+}
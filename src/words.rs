@@ -1,4 +1,34 @@
+use actix_web::dev::Payload;
+use actix_web::{FromRequest, HttpRequest, HttpResponse, ResponseError, web};
+use rand::Rng;
 use rand::seq::IndexedRandom;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::future::{Ready, ready};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, LazyLock, Mutex};
+
+use crate::api::ApiError;
+use crate::room::{bearer_token, constant_time_eq};
+use crate::storage::SavedWordPack;
+
+/// Bumped whenever `WORDS` changes, since game codes only reproduce a board with the same list
+pub const WORDS_VERSION: u32 = 1;
+/// The fewest words a list can have and still fill a board
+pub const MIN_WORDS: usize = 25;
+/// Name of the pack of built-in words
+pub const CLASSIC_PACK: &str = "classic";
+const MAX_NAME_LENGTH: usize = 32;
+
+static CLASSIC: LazyLock<Arc<WordPack>> = LazyLock::new(|| {
+    Arc::new(WordPack {
+        name: CLASSIC_PACK.to_string(),
+        version: WORDS_VERSION,
+        words: WORDS.iter().map(|word| word.to_string()).collect(),
+        uploaded: false,
+    })
+});
 
 pub const WORDS: [&str; 400] = [
     "Hotel",
@@ -403,12 +433,912 @@ pub const WORDS: [&str; 400] = [
     "Mine",
 ];
 
-pub fn get_words() -> [&'static str; 25] {
-    let mut rng = rand::rng();
-    let mut result = [""; 25];
-    WORDS
-        .choose_multiple(&mut rng, result.len())
-        .zip(result.iter_mut())
-        .for_each(|(word, slot)| *slot = word);
-    result
+/// A named list of words that boards can be dealt from
+#[derive(Debug)]
+pub struct WordPack {
+    pub name: String,
+    /// Derived from the words themselves, so game codes made with a different list are refused
+    pub version: u32,
+    pub words: Vec<String>,
+    /// Whether the pack was uploaded through the API, rather than built in or loaded from a file;
+    /// only uploaded packs can be changed or deleted
+    pub uploaded: bool,
+}
+
+impl WordPack {
+    /// Reads a pack from a file, named after the file without its extension. The file is either a
+    /// JSON array of strings or one word per line; empty lines in the latter are skipped.
+    pub fn load(path: &Path) -> Result<Self, WordListError> {
+        let name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        Self::new(name, Self::read(path)?).map_err(|err| WordListError::Invalid {
+            path: path.to_path_buf(),
+            err,
+        })
+    }
+
+    /// Checks the name, and the words for blanks, duplicates and too few entries. Words are
+    /// trimmed of surrounding whitespace.
+    pub fn new(name: String, words: Vec<String>) -> Result<Self, WordPackError> {
+        let valid_name = !name.is_empty()
+            && name.len() <= MAX_NAME_LENGTH
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid_name {
+            return Err(WordPackError::InvalidName);
+        }
+
+        let words: Vec<String> = words.iter().map(|word| word.trim().to_string()).collect();
+        let mut seen = HashSet::new();
+        for word in &words {
+            if word.is_empty() {
+                return Err(WordPackError::BlankWord);
+            }
+            if !seen.insert(word.to_lowercase()) {
+                return Err(WordPackError::DuplicateWord(word.clone()));
+            }
+        }
+        if words.len() < MIN_WORDS {
+            return Err(WordPackError::TooSmall(words.len()));
+        }
+
+        Ok(Self {
+            name,
+            version: short_hash(
+                words.iter().flat_map(|word| word.bytes().chain([b'\n'])),
+                PACK_VERSION_DIGITS,
+            ),
+            words,
+            uploaded: false,
+        })
+    }
+
+    fn read(path: &Path) -> Result<Vec<String>, WordListError> {
+        let contents = std::fs::read_to_string(path).map_err(|err| WordListError::Io {
+            path: path.to_path_buf(),
+            err,
+        })?;
+
+        // Numbered from 1: positions in a JSON array, or line numbers
+        let entries: Vec<(usize, String)> = if contents.trim_start().starts_with('[') {
+            let entries: Vec<String> =
+                serde_json::from_str(&contents).map_err(|err| WordListError::Json {
+                    path: path.to_path_buf(),
+                    err,
+                })?;
+            (1..).zip(entries).collect()
+        } else {
+            (1..)
+                .zip(contents.lines())
+                .filter(|(_, line)| !line.is_empty())
+                .map(|(number, line)| (number, line.to_string()))
+                .collect()
+        };
+
+        entries
+            .into_iter()
+            .map(|(entry, word)| {
+                let word = word.trim();
+                if word.is_empty() {
+                    Err(WordListError::Blank {
+                        path: path.to_path_buf(),
+                        entry,
+                    })
+                } else {
+                    Ok(word.to_string())
+                }
+            })
+            .collect()
+    }
+
+    fn summary(&self, default: &[PackWeight]) -> WordPackSummary {
+        WordPackSummary {
+            name: self.name.clone(),
+            words: self.words.len(),
+            default: default.iter().any(|chosen| chosen.pack == self.name),
+            uploaded: self.uploaded,
+        }
+    }
+}
+
+/// Pack versions have five digits and the versions of mixes six, so a code dealt from a mix can't
+/// be replayed from a single pack, and neither can be mistaken for the built-in list's version
+const PACK_VERSION_DIGITS: u32 = 5;
+const MIX_VERSION_DIGITS: u32 = 6;
+
+// An FNV-1a hash with exactly `digits` decimal digits
+fn short_hash(bytes: impl Iterator<Item = u8>, digits: u32) -> u32 {
+    let hash = bytes.fold(0x811c9dc5u32, |hash, byte| {
+        (hash ^ byte as u32).wrapping_mul(0x01000193)
+    });
+    let lowest = 10u32.pow(digits - 1);
+    lowest + hash % (9 * lowest)
+}
+
+#[derive(Debug)]
+pub enum WordListError {
+    Io {
+        path: PathBuf,
+        err: std::io::Error,
+    },
+    Json {
+        path: PathBuf,
+        err: serde_json::Error,
+    },
+    /// `entry` is the line number in a text file
+    Blank {
+        path: PathBuf,
+        entry: usize,
+    },
+    Invalid {
+        path: PathBuf,
+        err: WordPackError,
+    },
+}
+
+impl std::fmt::Display for WordListError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WordListError::Io { path, err } => {
+                write!(f, "couldn't read the word list {}: {err}", path.display())
+            }
+            WordListError::Json { path, err } => {
+                write!(f, "{} isn't a JSON array of strings: {err}", path.display())
+            }
+            WordListError::Blank { path, entry } => {
+                write!(f, "entry {entry} of {} is blank", path.display())
+            }
+            WordListError::Invalid { path, err } => {
+                write!(f, "the word list {} is invalid: {err}", path.display())
+            }
+        }
+    }
+}
+
+impl std::error::Error for WordListError {}
+
+/// A pack chosen for a game, along with how much of the board it should make up relative to the
+/// other packs chosen
+#[derive(Debug, Clone, Deserialize)]
+pub struct PackWeight {
+    pub pack: String,
+    #[serde(default = "PackWeight::default_weight")]
+    pub weight: f64,
+}
+
+impl PackWeight {
+    fn default_weight() -> f64 {
+        1.0
+    }
+}
+
+/// The words a board is dealt from: one or more packs, weighted
+#[derive(Debug, Clone)]
+pub struct WordSource {
+    packs: Vec<(Arc<WordPack>, f64)>,
+}
+
+impl WordSource {
+    /// Just the built-in words
+    pub fn classic() -> Self {
+        Self {
+            packs: vec![(CLASSIC.clone(), 1.0)],
+        }
+    }
+
+    /// The version that game codes dealt from these words carry. A single pack keeps its own
+    /// version, whatever its weight; a mix gets one derived from its packs and weights.
+    pub fn version(&self) -> u32 {
+        match self.packs.as_slice() {
+            [(pack, _)] => pack.version,
+            packs => short_hash(
+                packs.iter().flat_map(|(pack, weight)| {
+                    pack.version
+                        .to_le_bytes()
+                        .into_iter()
+                        .chain(weight.to_bits().to_le_bytes())
+                }),
+                MIX_VERSION_DIGITS,
+            ),
+        }
+    }
+
+    /// Picks the words for a board. Each pack's share of the board follows its weight, whatever
+    /// its size, and a word in more than one pack is only dealt once.
+    pub fn deal(&self, rng: &mut impl Rng) -> [String; 25] {
+        let mut result: [String; 25] = Default::default();
+        if let [(pack, _)] = self.packs.as_slice() {
+            pack.words
+                .choose_multiple(rng, result.len())
+                .zip(result.iter_mut())
+                .for_each(|(word, slot)| slot.clone_from(word));
+        } else {
+            self.candidates()
+                .choose_multiple_weighted(rng, result.len(), |(_, weight)| *weight)
+                // Weights are checked to be positive when the packs are chosen
+                .unwrap()
+                .zip(result.iter_mut())
+                .for_each(|((word, _), slot)| *slot = word.to_string());
+        }
+        result
+    }
+
+    // Every distinct word, weighted so that each pack's words add up to the pack's weight
+    fn candidates(&self) -> Vec<(&str, f64)> {
+        let mut seen = HashSet::new();
+        self.packs
+            .iter()
+            .flat_map(|(pack, weight)| {
+                let weight = weight / pack.words.len() as f64;
+                pack.words.iter().map(move |word| (word.as_str(), weight))
+            })
+            .filter(|(word, _)| seen.insert(word.to_lowercase()))
+            .collect()
+    }
+}
+
+/// A word pack as listed to clients
+#[derive(Debug, Clone, Serialize)]
+pub struct WordPackSummary {
+    pub name: String,
+    pub words: usize,
+    /// Whether games that don't choose packs are dealt from this one
+    pub default: bool,
+    pub uploaded: bool,
+}
+
+/// The word packs every room can deal from
+#[derive(Debug, Clone)]
+pub struct WordPacks {
+    packs: Arc<Mutex<BTreeMap<String, Arc<WordPack>>>>,
+    default: Arc<Vec<PackWeight>>,
+    /// Secret that grants access to uploading, changing and deleting packs, which are refused to
+    /// everyone without one
+    admin_token: Option<Arc<String>>,
+    // Counts changes to the uploaded packs, so saving can tell when they need writing again
+    revision: Arc<AtomicU64>,
+}
+
+impl WordPacks {
+    /// The built-in pack along with `custom` ones. Games that don't choose packs are dealt from all
+    /// of the custom packs evenly, or from the built-in one if there are none.
+    pub fn new(custom: Vec<WordPack>, admin_token: Option<String>) -> Result<Self, WordPackError> {
+        let default = if custom.is_empty() {
+            vec![PackWeight {
+                pack: CLASSIC_PACK.to_string(),
+                weight: 1.0,
+            }]
+        } else {
+            custom
+                .iter()
+                .map(|pack| PackWeight {
+                    pack: pack.name.clone(),
+                    weight: 1.0,
+                })
+                .collect()
+        };
+
+        let mut packs = BTreeMap::from([(CLASSIC_PACK.to_string(), CLASSIC.clone())]);
+        for pack in custom {
+            Self::check_name_free(&packs, &pack.name)?;
+            Self::check_version_free(&packs, &pack)?;
+            packs.insert(pack.name.clone(), Arc::new(pack));
+        }
+
+        Ok(Self {
+            packs: Arc::new(Mutex::new(packs)),
+            default: Arc::new(default),
+            admin_token: admin_token.map(Arc::new),
+            revision: Arc::new(AtomicU64::new(0)),
+        })
+    }
+
+    // Names are compared case-insensitively, so packs can't be told apart by case alone
+    fn check_name_free(
+        packs: &BTreeMap<String, Arc<WordPack>>,
+        name: &str,
+    ) -> Result<(), WordPackError> {
+        match packs.keys().find(|taken| taken.eq_ignore_ascii_case(name)) {
+            Some(taken) => Err(WordPackError::NameTaken(taken.clone())),
+            None => Ok(()),
+        }
+    }
+
+    // Codes name their pack by version alone, so no two packs may share one. Identical word lists
+    // always do; different ones only rarely, and changing a word is enough to tell them apart.
+    fn check_version_free(
+        packs: &BTreeMap<String, Arc<WordPack>>,
+        pack: &WordPack,
+    ) -> Result<(), WordPackError> {
+        match packs
+            .values()
+            .find(|other| other.name != pack.name && other.version == pack.version)
+        {
+            Some(other) => Err(WordPackError::VersionTaken(other.name.clone())),
+            None => Ok(()),
+        }
+    }
+
+    /// Checks the admin token sent as a bearer token
+    pub fn authorize(&self, req: &HttpRequest) -> Result<(), WordPackError> {
+        let admin_token = self
+            .admin_token
+            .as_ref()
+            .ok_or(WordPackError::NoAdminToken)?;
+        match bearer_token(req) {
+            Some(token) if constant_time_eq(token.as_bytes(), admin_token.as_bytes()) => Ok(()),
+            _ => Err(WordPackError::Unauthorized),
+        }
+    }
+
+    pub fn summaries(&self) -> Vec<WordPackSummary> {
+        self.packs
+            .lock()
+            .unwrap()
+            .values()
+            .map(|pack| pack.summary(&self.default))
+            .collect()
+    }
+
+    pub fn get(&self, name: &str) -> Result<Arc<WordPack>, WordPackError> {
+        self.packs
+            .lock()
+            .unwrap()
+            .get(name)
+            .cloned()
+            .ok_or_else(|| WordPackError::NotFound(name.to_string()))
+    }
+
+    /// Adds an uploaded pack, which new games can choose straight away
+    pub fn create(
+        &self,
+        name: String,
+        words: Vec<String>,
+    ) -> Result<WordPackSummary, WordPackError> {
+        let pack = WordPack {
+            uploaded: true,
+            ..WordPack::new(name, words)?
+        };
+
+        let mut packs = self.packs.lock().unwrap();
+        Self::check_name_free(&packs, &pack.name)?;
+        Self::check_version_free(&packs, &pack)?;
+        let summary = pack.summary(&self.default);
+        packs.insert(pack.name.clone(), Arc::new(pack));
+        self.revision.fetch_add(1, Ordering::SeqCst);
+        Ok(summary)
+    }
+
+    /// Replaces the words of an uploaded pack. Games already dealt from it keep their boards, but
+    /// their codes no longer replay, since the pack's version changes with its words.
+    pub fn update(&self, name: &str, words: Vec<String>) -> Result<WordPackSummary, WordPackError> {
+        let mut packs = self.packs.lock().unwrap();
+        Self::check_uploaded(&packs, name)?;
+
+        let pack = WordPack {
+            uploaded: true,
+            ..WordPack::new(name.to_string(), words)?
+        };
+        Self::check_version_free(&packs, &pack)?;
+        let summary = pack.summary(&self.default);
+        packs.insert(pack.name.clone(), Arc::new(pack));
+        self.revision.fetch_add(1, Ordering::SeqCst);
+        Ok(summary)
+    }
+
+    /// Removes an uploaded pack
+    pub fn delete(&self, name: &str) -> Result<(), WordPackError> {
+        let mut packs = self.packs.lock().unwrap();
+        Self::check_uploaded(&packs, name)?;
+        packs.remove(name);
+        self.revision.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn check_uploaded(
+        packs: &BTreeMap<String, Arc<WordPack>>,
+        name: &str,
+    ) -> Result<(), WordPackError> {
+        match packs.get(name) {
+            Some(pack) if pack.uploaded => Ok(()),
+            Some(_) => Err(WordPackError::ReadOnly(name.to_string())),
+            None => Err(WordPackError::NotFound(name.to_string())),
+        }
+    }
+
+    pub fn revision(&self) -> u64 {
+        self.revision.load(Ordering::SeqCst)
+    }
+
+    /// The uploaded packs, which are saved along with the rooms
+    pub fn save(&self) -> Vec<SavedWordPack> {
+        self.packs
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|pack| pack.uploaded)
+            .map(|pack| SavedWordPack {
+                name: pack.name.clone(),
+                words: pack.words.clone(),
+            })
+            .collect()
+    }
+
+    /// Brings back packs uploaded before a restart, returning how many could be restored
+    pub fn restore(&self, saved: Vec<SavedWordPack>) -> usize {
+        let mut restored = 0;
+        for saved in saved {
+            if let Err(err) = self.create(saved.name.clone(), saved.words) {
+                log::warn!("Skipping saved word pack \"{}\": {err}", saved.name);
+                continue;
+            }
+            restored += 1;
+        }
+        restored
+    }
+
+    /// Where games that don't choose packs are dealt from
+    pub fn default_source(&self) -> WordSource {
+        self.select(&[]).unwrap_or_else(|_| WordSource::classic())
+    }
+
+    /// The single pack with `version`, so a game code can be replayed without naming its pack
+    pub fn with_version(&self, version: u32) -> Option<WordSource> {
+        let packs = self.packs.lock().unwrap();
+        let pack = packs.values().find(|pack| pack.version == version)?;
+        Some(WordSource {
+            packs: vec![(pack.clone(), 1.0)],
+        })
+    }
+
+    /// Looks up the chosen packs, or the default ones if none are chosen
+    pub fn select(&self, selection: &[PackWeight]) -> Result<WordSource, WordPackError> {
+        let selection = if selection.is_empty() {
+            &self.default
+        } else {
+            selection
+        };
+
+        let packs = self.packs.lock().unwrap();
+        let source = WordSource {
+            packs: selection
+                .iter()
+                .map(|chosen| {
+                    if !(chosen.weight.is_finite() && chosen.weight > 0.0) {
+                        return Err(WordPackError::InvalidWeight);
+                    }
+                    let pack = packs
+                        .get(&chosen.pack)
+                        .ok_or_else(|| WordPackError::NotFound(chosen.pack.clone()))?;
+                    Ok((pack.clone(), chosen.weight))
+                })
+                .collect::<Result<_, _>>()?,
+        };
+
+        let count = source.candidates().len();
+        if count < MIN_WORDS {
+            return Err(WordPackError::TooFewWords(count));
+        }
+        Ok(source)
+    }
+}
+
+/// The word packs, for a caller whose admin token has been checked
+pub struct WordPackAdmin(pub WordPacks);
+
+impl FromRequest for WordPackAdmin {
+    type Error = WordPackError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let packs = req
+            .app_data::<web::Data<WordPacks>>()
+            .expect("WordPacks is registered as app data");
+        ready(
+            packs
+                .authorize(req)
+                .map(|()| WordPackAdmin(WordPacks::clone(packs))),
+        )
+    }
+}
+
+/// A problem with a word pack, or with the packs chosen for a game
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WordPackError {
+    NotFound(String),
+    InvalidWeight,
+    TooFewWords(usize),
+    InvalidName,
+    NameTaken(String),
+    VersionTaken(String),
+    ReadOnly(String),
+    BlankWord,
+    DuplicateWord(String),
+    TooSmall(usize),
+    Unauthorized,
+    NoAdminToken,
+}
+
+impl ApiError for WordPackError {
+    fn kind(&self) -> &'static str {
+        match self {
+            WordPackError::NotFound(_) => "Word pack not found",
+            WordPackError::InvalidWeight | WordPackError::TooFewWords(_) => "Invalid word packs",
+            WordPackError::InvalidName => "Invalid word pack name",
+            WordPackError::NameTaken(_) => "Word pack name taken",
+            WordPackError::VersionTaken(_) => "Word pack version taken",
+            WordPackError::ReadOnly(_) => "Word pack read-only",
+            WordPackError::BlankWord
+            | WordPackError::DuplicateWord(_)
+            | WordPackError::TooSmall(_) => "Invalid word pack",
+            WordPackError::Unauthorized => "Unauthorized",
+            WordPackError::NoAdminToken => "Word pack uploads disabled",
+        }
+    }
+}
+
+impl std::fmt::Display for WordPackError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WordPackError::NotFound(name) => write!(f, "There is no word pack called \"{name}\"."),
+            WordPackError::InvalidWeight => {
+                write!(f, "Word pack weights must be positive numbers.")
+            }
+            WordPackError::TooFewWords(count) => write!(
+                f,
+                "The chosen word packs only have {count} different words, \
+                 but a board needs {MIN_WORDS}."
+            ),
+            WordPackError::InvalidName => write!(
+                f,
+                "Word pack names must be 1 to {MAX_NAME_LENGTH} letters, digits, dashes \
+                 or underscores."
+            ),
+            WordPackError::NameTaken(name) => {
+                write!(f, "There is already a word pack called \"{name}\".")
+            }
+            WordPackError::VersionTaken(name) => write!(
+                f,
+                "The word pack's version is the same as \"{name}\"'s, so game codes couldn't \
+                 tell them apart; packs with the same words can't both be added."
+            ),
+            WordPackError::ReadOnly(name) => write!(
+                f,
+                "The word pack \"{name}\" wasn't uploaded, so it can't be changed or deleted."
+            ),
+            WordPackError::BlankWord => write!(f, "Word packs can't contain blank words."),
+            WordPackError::DuplicateWord(word) => {
+                write!(f, "\"{word}\" appears more than once in the word pack.")
+            }
+            WordPackError::TooSmall(count) => write!(
+                f,
+                "The word pack has {count} words, but a board needs at least {MIN_WORDS}."
+            ),
+            WordPackError::Unauthorized => write!(f, "A valid admin token is required."),
+            WordPackError::NoAdminToken => write!(
+                f,
+                "The server was started without an admin token, so word packs can't be uploaded, \
+                 changed or deleted."
+            ),
+        }
+    }
+}
+
+impl std::error::Error for WordPackError {}
+
+impl ResponseError for WordPackError {
+    fn error_response(&self) -> HttpResponse {
+        let mut response = match self {
+            WordPackError::NotFound(_) => HttpResponse::NotFound(),
+            WordPackError::InvalidWeight
+            | WordPackError::TooFewWords(_)
+            | WordPackError::InvalidName
+            | WordPackError::BlankWord
+            | WordPackError::DuplicateWord(_)
+            | WordPackError::TooSmall(_) => HttpResponse::BadRequest(),
+            WordPackError::NameTaken(_) | WordPackError::VersionTaken(_) => {
+                HttpResponse::Conflict()
+            }
+            WordPackError::ReadOnly(_) | WordPackError::NoAdminToken => HttpResponse::Forbidden(),
+            WordPackError::Unauthorized => HttpResponse::Unauthorized(),
+        };
+        response.json(self.body())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    fn numbered(prefix: &str, count: usize) -> Vec<String> {
+        (1..=count).map(|n| format!("{prefix}{n}")).collect()
+    }
+
+    fn pack(name: &str, words: Vec<String>) -> WordPack {
+        WordPack::new(name.to_string(), words).unwrap()
+    }
+
+    fn chosen(pack: &str, weight: f64) -> PackWeight {
+        PackWeight {
+            pack: pack.to_string(),
+            weight,
+        }
+    }
+
+    // Writes `contents` to a file called `file_name` in a directory of its own for `test`
+    fn word_file(test: &str, file_name: &str, contents: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("codenames-{test}-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(file_name);
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn trims_words_and_versions_packs_by_their_words() {
+        let mut words = numbered("word", MIN_WORDS);
+        words[0] = "  word1\t".to_string();
+        let pack = WordPack::new("pack".to_string(), words).unwrap();
+        assert_eq!(pack.words, numbered("word", MIN_WORDS));
+
+        let renamed = WordPack::new("other".to_string(), numbered("word", MIN_WORDS)).unwrap();
+        assert_eq!(renamed.version, pack.version);
+        assert_eq!(
+            renamed.version.to_string().len(),
+            PACK_VERSION_DIGITS as usize
+        );
+        let changed = WordPack::new("pack".to_string(), numbered("term", MIN_WORDS)).unwrap();
+        assert_ne!(changed.version, pack.version);
+    }
+
+    #[test]
+    fn refuses_blank_and_duplicate_words() {
+        let mut words = numbered("word", MIN_WORDS);
+        words.push(" ".to_string());
+        assert_eq!(
+            WordPack::new("pack".to_string(), words).unwrap_err(),
+            WordPackError::BlankWord
+        );
+
+        let mut words = numbered("word", MIN_WORDS);
+        words.push("WORD3 ".to_string());
+        assert_eq!(
+            WordPack::new("pack".to_string(), words).unwrap_err(),
+            WordPackError::DuplicateWord("WORD3".to_string())
+        );
+    }
+
+    #[test]
+    fn refuses_packs_too_small_for_a_board() {
+        assert_eq!(
+            WordPack::new("pack".to_string(), numbered("word", MIN_WORDS - 1)).unwrap_err(),
+            WordPackError::TooSmall(MIN_WORDS - 1)
+        );
+    }
+
+    #[test]
+    fn refuses_invalid_pack_names() {
+        for name in ["", "has space", "slash/", &"x".repeat(MAX_NAME_LENGTH + 1)] {
+            assert_eq!(
+                WordPack::new(name.to_string(), numbered("word", MIN_WORDS)).unwrap_err(),
+                WordPackError::InvalidName,
+                "{name:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn loads_json_and_line_per_word_files_alike() {
+        let words = numbered("word", MIN_WORDS);
+        let json = word_file(
+            "load",
+            "json-pack.json",
+            &serde_json::to_string(&words).unwrap(),
+        );
+        let lines = word_file("load", "lines.txt", &format!("\n{}\n\n", words.join("\n")));
+
+        let json = WordPack::load(&json).unwrap();
+        let lines = WordPack::load(&lines).unwrap();
+        assert_eq!(json.name, "json-pack");
+        assert_eq!(lines.name, "lines");
+        assert_eq!(json.words, words);
+        assert_eq!(lines.words, words);
+        assert_eq!(json.version, lines.version);
+        assert!(!json.uploaded);
+    }
+
+    #[test]
+    fn reports_where_a_word_file_is_wrong() {
+        let mut words = numbered("word", MIN_WORDS);
+        words.insert(2, " ".to_string());
+        let blank = word_file("bad", "blank.json", &serde_json::to_string(&words).unwrap());
+        assert!(matches!(
+            WordPack::load(&blank),
+            Err(WordListError::Blank { entry: 3, .. })
+        ));
+
+        let blank = word_file("bad", "blank.txt", "word1\n\n  \nword2\n");
+        assert!(matches!(
+            WordPack::load(&blank),
+            Err(WordListError::Blank { entry: 3, .. })
+        ));
+
+        let not_strings = word_file("bad", "numbers.json", "[1, 2, 3]");
+        assert!(matches!(
+            WordPack::load(&not_strings),
+            Err(WordListError::Json { .. })
+        ));
+
+        let mut words = numbered("word", MIN_WORDS);
+        words.push("Word1".to_string());
+        let duplicate = word_file("bad", "duplicate.txt", &words.join("\n"));
+        assert!(matches!(
+            WordPack::load(&duplicate),
+            Err(WordListError::Invalid {
+                err: WordPackError::DuplicateWord(_),
+                ..
+            })
+        ));
+
+        let missing = blank.with_file_name("missing.txt");
+        assert!(matches!(
+            WordPack::load(&missing),
+            Err(WordListError::Io { .. })
+        ));
+    }
+
+    #[test]
+    fn refuses_packs_whose_versions_clash() {
+        let words = numbered("word", MIN_WORDS);
+        assert_eq!(
+            WordPacks::new(
+                vec![pack("first", words.clone()), pack("second", words.clone())],
+                Some("token".to_string())
+            )
+            .unwrap_err(),
+            WordPackError::VersionTaken("first".to_string())
+        );
+
+        let packs = WordPacks::new(
+            vec![pack("first", words.clone())],
+            Some("token".to_string()),
+        )
+        .unwrap();
+        assert_eq!(
+            packs.create("second".to_string(), words).unwrap_err(),
+            WordPackError::VersionTaken("first".to_string())
+        );
+    }
+
+    #[test]
+    fn refuses_selections_that_cant_deal_a_board() {
+        let packs = WordPacks::new(vec![pack("nouns", numbered("noun", MIN_WORDS))], None).unwrap();
+        for weight in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            assert_eq!(
+                packs.select(&[chosen("nouns", weight)]).unwrap_err(),
+                WordPackError::InvalidWeight,
+                "{weight}"
+            );
+        }
+        assert_eq!(
+            packs.select(&[chosen("verbs", 1.0)]).unwrap_err(),
+            WordPackError::NotFound("verbs".to_string())
+        );
+    }
+
+    #[test]
+    fn selects_the_default_packs_when_none_are_chosen() {
+        let nouns = pack("nouns", numbered("noun", MIN_WORDS));
+        let version = nouns.version;
+        let packs = WordPacks::new(vec![nouns], None).unwrap();
+        assert_eq!(packs.select(&[]).unwrap().version(), version);
+        assert_eq!(packs.with_version(version).unwrap().version(), version);
+
+        let packs = WordPacks::new(Vec::new(), None).unwrap();
+        assert_eq!(packs.select(&[]).unwrap().version(), WORDS_VERSION);
+    }
+
+    #[test]
+    fn gives_mixes_their_own_versions() {
+        let packs = WordPacks::new(
+            vec![
+                pack("nouns", numbered("noun", MIN_WORDS)),
+                pack("verbs", numbered("verb", MIN_WORDS)),
+            ],
+            None,
+        )
+        .unwrap();
+        let even = packs
+            .select(&[chosen("nouns", 1.0), chosen("verbs", 1.0)])
+            .unwrap()
+            .version();
+        let uneven = packs
+            .select(&[chosen("nouns", 2.0), chosen("verbs", 1.0)])
+            .unwrap()
+            .version();
+        assert_eq!(even.to_string().len(), MIX_VERSION_DIGITS as usize);
+        assert_ne!(even, uneven);
+        assert!(packs.with_version(even).is_none());
+    }
+
+    #[test]
+    fn deals_each_shared_word_once() {
+        // Between them the packs have just one more word than a board needs
+        let mut shouted: Vec<String> = numbered("WORD", MIN_WORDS);
+        shouted[0] = "extra".to_string();
+        let packs = WordPacks::new(
+            vec![
+                pack("quiet", numbered("word", MIN_WORDS)),
+                pack("loud", shouted),
+            ],
+            None,
+        )
+        .unwrap();
+        let source = packs
+            .select(&[chosen("quiet", 1.0), chosen("loud", 1.0)])
+            .unwrap();
+        assert_eq!(source.candidates().len(), MIN_WORDS + 1);
+
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        for _ in 0..100 {
+            let board = source.deal(&mut rng);
+            let distinct: HashSet<String> = board.iter().map(|word| word.to_lowercase()).collect();
+            assert_eq!(distinct.len(), board.len(), "{board:?}");
+        }
+    }
+
+    #[test]
+    fn deals_packs_in_proportion_to_their_weights() {
+        let packs = WordPacks::new(
+            vec![
+                pack("nouns", numbered("noun", 100)),
+                pack("verbs", numbered("verb", MIN_WORDS)),
+            ],
+            None,
+        )
+        .unwrap();
+        let source = packs
+            .select(&[chosen("nouns", 1.0), chosen("verbs", 3.0)])
+            .unwrap();
+
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        let boards = 200;
+        let verbs: usize = (0..boards)
+            .map(|_| {
+                let board = source.deal(&mut rng);
+                board.iter().filter(|word| word.starts_with("verb")).count()
+            })
+            .sum();
+        // Three quarters of the board would be 18.75 verbs, though a board only takes so many
+        // words from a pack before the rest of it weighs more
+        let average = verbs as f64 / boards as f64;
+        assert!((14.0..20.0).contains(&average), "{average}");
+    }
+
+    #[test]
+    fn refuses_every_admin_without_an_admin_token() {
+        let req = |token: &str| {
+            actix_web::test::TestRequest::default()
+                .insert_header(("Authorization", format!("Bearer {token}")))
+                .to_http_request()
+        };
+        let packs = WordPacks::new(Vec::new(), Some("token".to_string())).unwrap();
+        assert_eq!(packs.authorize(&req("token")), Ok(()));
+        assert_eq!(
+            packs.authorize(&req("guess")),
+            Err(WordPackError::Unauthorized)
+        );
+
+        let packs = WordPacks::new(Vec::new(), None).unwrap();
+        assert_eq!(packs.authorize(&req("")), Err(WordPackError::NoAdminToken));
+        assert_eq!(
+            packs.authorize(&req("token")),
+            Err(WordPackError::NoAdminToken)
+        );
+    }
 }